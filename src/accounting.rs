@@ -0,0 +1,109 @@
+//! Before/after accounting for commands that change swap or memory usage
+//!
+//! Rather than printing only the absolute swap accounting stats after a
+//! command (and leaving the user to subtract the previous values by hand),
+//! commands can take an [`AccountingSnapshot`] before and after they run and
+//! print the difference.
+
+use crate::bytesize_display::ByteSizeDeltaDisplayGiB;
+use crate::bytesize_display::ByteSizeDisplayGiB;
+use crate::kstat::kstat_read_physmem;
+use crate::kstat::PhysicalMemoryStats;
+use crate::swap::AnonInfo;
+use anyhow::Context;
+use bytesize::ByteSize;
+use serde::Serialize;
+
+/// Swap and physical memory accounting at a point in time
+#[derive(Serialize)]
+pub struct AccountingSnapshot {
+    pub swapinfo: AnonInfo,
+    pub physmem: PhysicalMemoryStats,
+}
+
+impl AccountingSnapshot {
+    /// Collect the latest stats
+    pub fn fetch() -> Result<AccountingSnapshot, anyhow::Error> {
+        let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
+        let physmem =
+            kstat_read_physmem(&kstat).context("kstat_read_physmem")?;
+        let swapinfo = AnonInfo::fetch()?;
+        Ok(AccountingSnapshot { swapinfo, physmem })
+    }
+
+    /// Returns the named values in this snapshot, in the order they're
+    /// printed
+    fn values(&self) -> [(&'static str, ByteSize); 7] {
+        let swapinfo = &self.swapinfo;
+        [
+            ("swap total", swapinfo.total()),
+            ("swap available", swapinfo.available()),
+            ("swap used", swapinfo.allocated() + swapinfo.reserved()),
+            ("swap reserved", swapinfo.reserved()),
+            ("swap allocated", swapinfo.allocated()),
+            ("freemem", self.physmem.freemem),
+            ("availrmem", self.physmem.availrmem),
+        ]
+    }
+
+    /// Returns an object for displaying the change from `self` to `after`
+    pub fn delta<'a>(
+        &'a self,
+        after: &'a AccountingSnapshot,
+    ) -> AccountingDelta<'a> {
+        AccountingDelta { before: self, after }
+    }
+}
+
+/// Describes the change in accounting stats between two snapshots
+///
+/// The `Display` impl prints a table of the values before and after, along
+/// with the change.  [`AccountingDelta::summary()`] prints only the changes
+/// on one line.
+pub struct AccountingDelta<'a> {
+    before: &'a AccountingSnapshot,
+    after: &'a AccountingSnapshot,
+}
+
+impl<'a> AccountingDelta<'a> {
+    fn changes(
+        &self,
+    ) -> impl Iterator<Item = (&'static str, ByteSize, ByteSize, i64)> {
+        self.before.values().into_iter().zip(self.after.values()).map(
+            |((name, before), (_, after))| {
+                let delta = (after.as_u64() as i64) - (before.as_u64() as i64);
+                (name, before, after, delta)
+            },
+        )
+    }
+
+    /// Returns a one-line summary of the changes (e.g., "swap reserved +10.0
+    /// GiB, swap available −10.0 GiB, ...")
+    pub fn summary(&self) -> String {
+        self.changes()
+            .map(|(name, _, _, delta)| {
+                format!("{} {}", name, ByteSizeDeltaDisplayGiB(delta))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl<'a> std::fmt::Display for AccountingDelta<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{:16} {:>12} {:>12} {:>12}\n",
+            "ACCOUNTING", "BEFORE (GiB)", "AFTER (GiB)", "DELTA"
+        ))?;
+        for (name, before, after, delta) in self.changes() {
+            f.write_fmt(format_args!(
+                "{:16} {:12} {:12} {:>12}\n",
+                name,
+                ByteSizeDisplayGiB(before),
+                ByteSizeDisplayGiB(after),
+                ByteSizeDeltaDisplayGiB(delta).to_string(),
+            ))?;
+        }
+        Ok(())
+    }
+}
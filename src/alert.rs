@@ -0,0 +1,343 @@
+//! Alerts evaluated by the monitor
+//!
+//! An alert is a [`Condition`] on the stats in each [`MonitorSample`], like
+//! `freemem < desfree` or `swap-available < 1GiB`.  While the monitor is
+//! enabled, it evaluates each alert against every sample and prints a
+//! one-line event whenever an alert's condition becomes true (and again when
+//! it stops being true).  An alert can also take an [`AlertAction`] when it
+//! fires.
+
+use crate::monitor::MonitorSample;
+use crate::pressure::PressureLevel;
+use crate::stats::Stat;
+use crate::stats::StatKind;
+use anyhow::anyhow;
+use anyhow::bail;
+use bytesize::ByteSize;
+use std::str::FromStr;
+
+/// One side of a [`Condition`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// the value of a stat in the current sample
+    Stat(Stat),
+    /// a constant (in bytes, for comparison with byte-valued stats)
+    Value(u64),
+}
+
+impl Operand {
+    /// Returns this operand's value in `sample` (see [`Stat::value()`])
+    pub(crate) fn value(&self, sample: &MonitorSample) -> Option<u64> {
+        match self {
+            Operand::Stat(stat) => stat.value(sample),
+            Operand::Value(value) => Some(*value),
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = anyhow::Error;
+
+    /// Parses a stat name (like `freemem`), a size (like `1GiB` or `4096`),
+    /// or a pressure level (like `desperate`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(stat) = Stat::from_str(s) {
+            return Ok(Operand::Stat(stat));
+        }
+        if let Some(level) = PressureLevel::ALL.iter().find(|l| l.name() == s) {
+            return Ok(Operand::Value(*level as u64));
+        }
+        ByteSize::from_str(s).map(|b| Operand::Value(b.as_u64())).map_err(
+            |_| {
+                anyhow!(
+                    "expected a stat name, size, or pressure level, found {:?}",
+                    s
+                )
+            },
+        )
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Stat(stat) => write!(f, "{}", stat),
+            Operand::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// How the two sides of a [`Condition`] are compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    // Two-character operators come first so that "<=" isn't taken for "<".
+    pub(crate) const ALL: &'static [Comparison] = &[
+        Comparison::Le,
+        Comparison::Ge,
+        Comparison::Eq,
+        Comparison::Ne,
+        Comparison::Lt,
+        Comparison::Gt,
+    ];
+
+    pub(crate) fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+        }
+    }
+
+    pub(crate) fn compare<T: PartialOrd>(&self, lhs: T, rhs: T) -> bool {
+        match self {
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+        }
+    }
+}
+
+/// A comparison between two stats (or a stat and a constant) in a sample
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: Comparison,
+    pub rhs: Operand,
+}
+
+impl Condition {
+    /// Returns whether this condition holds for `sample`
+    ///
+    /// A condition on a stat that's unavailable in `sample` doesn't hold.
+    pub fn eval(&self, sample: &MonitorSample) -> bool {
+        match (self.lhs.value(sample), self.rhs.value(sample)) {
+            (Some(lhs), Some(rhs)) => self.op.compare(lhs, rhs),
+            _ => false,
+        }
+    }
+
+    /// Describes the values of the stats in this condition in `sample`
+    pub fn describe_values(&self, sample: &MonitorSample) -> String {
+        [&self.lhs, &self.rhs]
+            .iter()
+            .filter_map(|operand| match operand {
+                Operand::Stat(stat) => {
+                    let value = match stat.value(sample) {
+                        Some(value) => value,
+                        None => return Some(format!("{}=-", stat)),
+                    };
+                    Some(match stat.kind() {
+                        StatKind::Bytes => {
+                            format!("{}={}", stat, ByteSize::b(value))
+                        }
+                        StatKind::Counter => format!("{}={}", stat, value),
+                        StatKind::Pressure => {
+                            match PressureLevel::from_u64(value) {
+                                Some(level) => format!("{}={}", stat, level),
+                                None => format!("{}={}", stat, value),
+                            }
+                        }
+                    })
+                }
+                Operand::Value(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    /// Parses a condition like `freemem < desfree` (whitespace is optional)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (op, index) = Comparison::ALL
+            .iter()
+            .find_map(|op| s.find(op.symbol()).map(|i| (*op, i)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "condition {:?}: expected a comparison (<, <=, >, >=, ==, \
+                    or !=)",
+                    s
+                )
+            })?;
+        let lhs_str = s[..index].trim();
+        let rhs_str = s[index + op.symbol().len()..].trim();
+        if lhs_str.is_empty() || rhs_str.is_empty() {
+            bail!("condition {:?}: expected a value on each side", s);
+        }
+        Ok(Condition {
+            lhs: Operand::from_str(lhs_str)?,
+            op,
+            rhs: Operand::from_str(rhs_str)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op.symbol(), self.rhs)
+    }
+}
+
+/// What to do (besides printing an event) when an alert fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertAction {
+    /// just print the event
+    Print,
+    /// stop any touches in progress (foreground or background)
+    StopTouches,
+}
+
+impl std::fmt::Display for AlertAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertAction::Print => f.pad("print"),
+            AlertAction::StopTouches => f.pad("stop-touches"),
+        }
+    }
+}
+
+/// A condition that the monitor checks on every sample
+#[derive(Clone, Debug)]
+pub struct Alert {
+    pub id: usize,
+    pub condition: Condition,
+    pub action: AlertAction,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Comparison;
+    use super::Condition;
+    use super::Operand;
+    use crate::kstat::PhysicalMemoryStats;
+    use crate::monitor::MonitorSample;
+    use crate::pressure::PressureLevel;
+    use crate::stats::Stat;
+    use crate::swap::AnonInfoSummary;
+    use bytesize::ByteSize;
+    use std::str::FromStr;
+    use std::time::Instant;
+    use std::time::SystemTime;
+
+    fn parse_err(s: &str) -> String {
+        Condition::from_str(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let c = Condition::from_str("freemem < desfree").unwrap();
+        assert_eq!(
+            c,
+            Condition {
+                lhs: Operand::Stat(Stat::Freemem),
+                op: Comparison::Lt,
+                rhs: Operand::Stat(Stat::Desfree),
+            }
+        );
+        assert_eq!(c.to_string(), "freemem < desfree");
+
+        // Whitespace is optional, and "<=" isn't taken for "<".
+        let c = Condition::from_str("swap-available<=1GiB").unwrap();
+        assert_eq!(c.lhs, Operand::Stat(Stat::SwapAvailable));
+        assert_eq!(c.op, Comparison::Le);
+        assert_eq!(c.rhs, Operand::Value(1024 * 1024 * 1024));
+
+        for (s, op) in [
+            ("a-b", None),
+            ("freemem > 4096", Some(Comparison::Gt)),
+            ("freemem >= 4096", Some(Comparison::Ge)),
+            ("freemem == 4096", Some(Comparison::Eq)),
+            ("freemem != 4096", Some(Comparison::Ne)),
+        ] {
+            assert_eq!(Condition::from_str(s).ok().map(|c| c.op), op, "{}", s);
+        }
+
+        let c = Condition::from_str("pressure >= desperate").unwrap();
+        assert_eq!(c.rhs, Operand::Value(PressureLevel::Desperate as u64));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_err("freemem"),
+            "condition \"freemem\": expected a comparison (<, <=, >, >=, ==, \
+            or !=)"
+        );
+        assert_eq!(
+            parse_err("freemem <"),
+            "condition \"freemem <\": expected a value on each side"
+        );
+        assert_eq!(
+            parse_err(" < 1GiB"),
+            "condition \" < 1GiB\": expected a value on each side"
+        );
+        assert_eq!(
+            parse_err("bogus < 1GiB"),
+            "expected a stat name, size, or pressure level, found \"bogus\""
+        );
+        assert!(Condition::from_str("freemem < 1 GiB extra").is_err());
+    }
+
+    #[test]
+    fn test_eval_unavailable() {
+        // A sample in which none of the optional sources could be read
+        let gib = ByteSize::gib;
+        let sample = MonitorSample {
+            time: SystemTime::now(),
+            instant: Instant::now(),
+            physmem: PhysicalMemoryStats {
+                physmem: gib(16),
+                freemem: gib(4),
+                availrmem: gib(8),
+                lotsfree: gib(1),
+                desfree: ByteSize::mib(512),
+                minfree: ByteSize::mib(256),
+            },
+            swapinfo: AnonInfoSummary {
+                total: gib(8).as_u64(),
+                available: gib(6).as_u64(),
+                reserved: gib(1).as_u64(),
+                allocated: gib(1).as_u64(),
+            }
+            .into(),
+            vm: None,
+            kmem_reaps: None,
+            arc: None,
+            arc_shrinks: 0,
+            zone_swap_failures: None,
+        };
+
+        let c = Condition::from_str("freemem > lotsfree").unwrap();
+        assert!(c.eval(&sample));
+        assert_eq!(
+            c.describe_values(&sample),
+            "freemem=4.3 GB, lotsfree=1073.7 MB"
+        );
+
+        // Conditions on unavailable stats never hold, either way around.
+        let c = Condition::from_str("arc-size < 1GiB").unwrap();
+        assert!(!c.eval(&sample));
+        assert_eq!(c.describe_values(&sample), "arc-size=-");
+        assert!(!Condition::from_str("arc-size >= 0").unwrap().eval(&sample));
+        assert_eq!(Stat::KmemReaps.value(&sample), None);
+        assert_eq!(Stat::ArcShrinks.value(&sample), None);
+        assert_eq!(Stat::Freemem.delta(&sample, &sample), Some(0));
+    }
+}
@@ -22,9 +22,145 @@ impl Display for ByteSizeDisplayKiB {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let as_kib = (self.0.as_u64()) / bytesize::KIB;
         if let Some(width) = f.width() {
-            f.write_fmt(format_args!("{:width$.1}", as_kib, width = width))
+            f.write_fmt(format_args!("{:width$}", as_kib, width = width))
         } else {
             f.write_fmt(format_args!("{}", as_kib))
         }
     }
 }
+
+/// Formats a [`ByteSize`] for display as a floating-point number of Mebibytes
+pub struct ByteSizeDisplayMiB(pub ByteSize);
+impl Display for ByteSizeDisplayMiB {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let as_mib = (self.0.as_u64() as f64) / (bytesize::MIB as f64);
+        if let Some(width) = f.width() {
+            f.write_fmt(format_args!("{:width$.1}", as_mib, width = width))
+        } else {
+            f.write_fmt(format_args!("{:.1}", as_mib))
+        }
+    }
+}
+
+/// Formats a change in size (in bytes) as a signed floating-point number of
+/// Gibibytes (e.g., "+10.0 GiB", "−10.0 GiB", or "±0")
+pub struct ByteSizeDeltaDisplayGiB(pub i64);
+impl Display for ByteSizeDeltaDisplayGiB {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let as_gib = (self.0.unsigned_abs() as f64) / (bytesize::GIB as f64);
+        match self.0.signum() {
+            0 => f.write_str("±0"),
+            1 => f.write_fmt(format_args!("+{:.1} GiB", as_gib)),
+            _ => f.write_fmt(format_args!("−{:.1} GiB", as_gib)),
+        }
+    }
+}
+
+/// Units in which a [`ByteSize`] can be displayed (see [`ByteSizeDisplayIn`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Units {
+    Bytes,
+    KiB,
+    MiB,
+    GiB,
+    Pages,
+}
+
+impl Units {
+    pub const ALL: &'static [Units] =
+        &[Units::Bytes, Units::KiB, Units::MiB, Units::GiB, Units::Pages];
+
+    /// Returns the name used to select these units
+    pub fn name(&self) -> &'static str {
+        match self {
+            Units::Bytes => "b",
+            Units::KiB => "kib",
+            Units::MiB => "mib",
+            Units::GiB => "gib",
+            Units::Pages => "pages",
+        }
+    }
+
+    /// Returns the number of bytes in one of these units
+    pub fn bytes_per_unit(&self) -> u64 {
+        match self {
+            Units::Bytes => 1,
+            Units::KiB => bytesize::KIB,
+            Units::MiB => bytesize::MIB,
+            Units::GiB => bytesize::GIB,
+            Units::Pages => crate::PAGE_SIZE as u64,
+        }
+    }
+}
+
+impl std::str::FromStr for Units {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        Units::ALL.iter().find(|u| u.name() == lower).copied().ok_or_else(
+            || {
+                let names: Vec<&str> =
+                    Units::ALL.iter().map(|u| u.name()).collect();
+                anyhow::anyhow!(
+                    "unsupported units {:?} (expected one of: {})",
+                    s,
+                    names.join(", ")
+                )
+            },
+        )
+    }
+}
+
+impl Display for Units {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Formats a [`ByteSize`] for display in the given [`Units`]
+pub struct ByteSizeDisplayIn(pub ByteSize, pub Units);
+impl Display for ByteSizeDisplayIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = f.width().unwrap_or(0);
+        match self.1 {
+            Units::Bytes => {
+                f.write_fmt(format_args!("{:width$}", self.0.as_u64()))
+            }
+            Units::KiB => f.write_fmt(format_args!(
+                "{:width$}",
+                ByteSizeDisplayKiB(self.0)
+            )),
+            Units::MiB => f.write_fmt(format_args!(
+                "{:width$}",
+                ByteSizeDisplayMiB(self.0)
+            )),
+            Units::GiB => f.write_fmt(format_args!(
+                "{:width$}",
+                ByteSizeDisplayGiB(self.0)
+            )),
+            Units::Pages => f.write_fmt(format_args!(
+                "{:width$}",
+                self.0.as_u64() / (crate::PAGE_SIZE as u64)
+            )),
+        }
+    }
+}
+
+/// Formats a rate of change (in bytes per second, possibly negative) for
+/// display in the given [`Units`] per second
+pub struct RateDisplayIn(pub f64, pub Units);
+impl Display for RateDisplayIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = f.width().unwrap_or(0);
+        let value = self.0 / (self.1.bytes_per_unit() as f64);
+        match self.1 {
+            Units::Bytes | Units::Pages => {
+                f.write_fmt(format_args!("{:width$.0}", value))
+            }
+            Units::KiB | Units::MiB | Units::GiB => {
+                f.write_fmt(format_args!("{:width$.1}", value))
+            }
+        }
+    }
+}
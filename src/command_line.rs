@@ -0,0 +1,257 @@
+//! Parsing command lines typed at the REPL (or read from a script)
+//!
+//! A command line is split into words on whitespace, except within double
+//! quotes (which are removed).  It may end with one of these suffixes, which
+//! say where the command's output should go:
+//!
+//! * `> FILE`: write the output to FILE, replacing its contents
+//! * `>> FILE`: append the output to FILE
+//! * `| COMMAND`: run COMMAND with the shell, with the output on its stdin
+//!
+//! `>`, `>>`, and `|` are only recognized as separate, unquoted words, and `>`
+//! and `>>` only as the next-to-last word.  For commands whose arguments
+//! include a condition (like `wait-until freemem > lotsfree`), the first `>`
+//! is part of the condition, so `wait-until freemem > lotsfree > FILE` waits
+//! for the condition and writes the output to FILE.
+
+use anyhow::anyhow;
+use anyhow::bail;
+
+/// Where a command's output should go, other than the terminal
+#[derive(Clone, Debug, PartialEq)]
+pub enum Redirect {
+    /// write the output to a file
+    File { path: String, append: bool },
+    /// run a shell command with the output on its stdin
+    Pipe(String),
+}
+
+/// A parsed command line: the command's name and arguments and where its
+/// output should go
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandLine {
+    pub argv: Vec<String>,
+    pub redirect: Option<Redirect>,
+}
+
+impl CommandLine {
+    /// Parses `line`
+    ///
+    /// `has_condition` reports whether the named command's arguments include
+    /// a condition (see the module documentation).
+    pub fn parse(
+        line: &str,
+        has_condition: impl Fn(&str) -> bool,
+    ) -> Result<CommandLine, anyhow::Error> {
+        let mut words = split_words(line)?;
+
+        if let Some(i) = words.iter().position(|w| w.is_operator("|")) {
+            let command = line[words[i].end..].trim();
+            if command.is_empty() {
+                bail!("expected a command after \"|\"");
+            }
+            words.truncate(i);
+            let argv = words.into_iter().map(|w| w.text).collect();
+            let redirect = Some(Redirect::Pipe(command.to_string()));
+            return Ok(CommandLine { argv, redirect });
+        }
+
+        let takes_condition =
+            words.first().is_some_and(|name| has_condition(&name.text));
+        // Returns whether `word` is a redirection operator, given the
+        // arguments between the command name and it.
+        let is_redirect = |word: &Word, args: &[Word]| {
+            word.is_operator(">>")
+                || (word.is_operator(">")
+                    && (!takes_condition
+                        || args.iter().any(|a| a.has_comparison())))
+        };
+
+        let nwords = words.len();
+        if nwords >= 2 && is_redirect(&words[nwords - 1], &words[1..nwords - 1])
+        {
+            bail!("expected a file name after {:?}", words[nwords - 1].text);
+        }
+        let mut redirect = None;
+        if nwords >= 3 && is_redirect(&words[nwords - 2], &words[1..nwords - 2])
+        {
+            // This can't fail because we checked the length above.
+            let path = words.pop().unwrap().text;
+            let append = words.pop().unwrap().text == ">>";
+            redirect = Some(Redirect::File { path, append });
+        }
+
+        let argv = words.into_iter().map(|w| w.text).collect();
+        Ok(CommandLine { argv, redirect })
+    }
+}
+
+/// One word of a command line
+struct Word {
+    text: String,
+    /// whether any part of the word was quoted
+    quoted: bool,
+    /// byte offset in the line just past the end of the word
+    end: usize,
+}
+
+impl Word {
+    /// Returns whether this word is the unquoted operator `op`
+    fn is_operator(&self, op: &str) -> bool {
+        !self.quoted && self.text == op
+    }
+
+    /// Returns whether this word contains a comparison operator (so it's all
+    /// or part of a condition that already has one)
+    fn has_comparison(&self) -> bool {
+        self.text.contains(['<', '>', '=', '!'])
+    }
+}
+
+/// Splits a command line into words on whitespace, except within double
+/// quotes (which are removed)
+fn split_words(line: &str) -> Result<Vec<Word>, anyhow::Error> {
+    let mut words = Vec::new();
+    let mut word: Option<Word> = None;
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() && !in_quotes {
+            words.extend(word.take());
+            continue;
+        }
+
+        let w = word.get_or_insert_with(|| Word {
+            text: String::new(),
+            quoted: false,
+            end: i,
+        });
+        w.end = i + c.len_utf8();
+        if c == '"' {
+            in_quotes = !in_quotes;
+            w.quoted = true;
+        } else {
+            w.text.push(c);
+        }
+    }
+    if in_quotes {
+        return Err(anyhow!("unterminated quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandLine;
+    use super::Redirect;
+
+    fn parse(line: &str) -> CommandLine {
+        CommandLine::parse(line, |name| name == "wait-until").unwrap()
+    }
+
+    fn parse_err(line: &str) -> String {
+        CommandLine::parse(line, |name| name == "wait-until")
+            .unwrap_err()
+            .to_string()
+    }
+
+    fn file(path: &str, append: bool) -> Option<Redirect> {
+        Some(Redirect::File { path: path.to_string(), append })
+    }
+
+    #[test]
+    fn test_words() {
+        let line = parse("  swap-touch  0x1000 \"a b\" c\"d e\"f ");
+        assert_eq!(line.argv, ["swap-touch", "0x1000", "a b", "cd ef"]);
+        assert_eq!(line.redirect, None);
+        assert!(parse("").argv.is_empty());
+        assert_eq!(parse_err("echo \"abc"), "unterminated quote");
+    }
+
+    #[test]
+    fn test_redirect_file() {
+        let line = parse("kstat-dump > out.txt");
+        assert_eq!(line.argv, ["kstat-dump"]);
+        assert_eq!(line.redirect, file("out.txt", false));
+
+        let line = parse("swap-info >> \"my log\"");
+        assert_eq!(line.argv, ["swap-info"]);
+        assert_eq!(line.redirect, file("my log", true));
+
+        // Operators that aren't separate, unquoted words are arguments.
+        let line = parse("swap-info \">\" out.txt");
+        assert_eq!(line.argv, ["swap-info", ">", "out.txt"]);
+        assert_eq!(line.redirect, None);
+        let line = parse("swap-info >out.txt");
+        assert_eq!(line.argv, ["swap-info", ">out.txt"]);
+
+        // So are operators that aren't the next-to-last word.
+        let line = parse("swap-info > a b");
+        assert_eq!(line.argv, ["swap-info", ">", "a", "b"]);
+        assert_eq!(line.redirect, None);
+
+        assert_eq!(
+            parse_err("swap-info >"),
+            "expected a file name after \">\""
+        );
+        assert_eq!(parse("wait-until freemem >").argv.len(), 3);
+        assert_eq!(
+            parse_err("swap-info >>"),
+            "expected a file name after \">>\""
+        );
+    }
+
+    #[test]
+    fn test_redirect_condition() {
+        // The first ">" belongs to the condition.
+        let line = parse("wait-until freemem > lotsfree");
+        assert_eq!(line.argv, ["wait-until", "freemem", ">", "lotsfree"]);
+        assert_eq!(line.redirect, None);
+        let line = parse("wait-until freemem > lotsfree --timeout 2m");
+        assert_eq!(line.redirect, None);
+        assert_eq!(line.argv.len(), 6);
+
+        let line = parse("wait-until freemem > lotsfree > out.txt");
+        assert_eq!(line.argv, ["wait-until", "freemem", ">", "lotsfree"]);
+        assert_eq!(line.redirect, file("out.txt", false));
+        let line = parse("wait-until freemem>lotsfree > out.txt");
+        assert_eq!(line.argv, ["wait-until", "freemem>lotsfree"]);
+        assert_eq!(line.redirect, file("out.txt", false));
+        let line = parse("wait-until \"freemem > lotsfree\" > out.txt");
+        assert_eq!(line.argv, ["wait-until", "freemem > lotsfree"]);
+        assert_eq!(line.redirect, file("out.txt", false));
+
+        // ">>" is never a comparison.
+        let line = parse("wait-until freemem >> out.txt");
+        assert_eq!(line.argv, ["wait-until", "freemem"]);
+        assert_eq!(line.redirect, file("out.txt", true));
+
+        // Other commands don't take conditions.
+        let line = parse("swap-info > lotsfree");
+        assert_eq!(line.redirect, file("lotsfree", false));
+    }
+
+    #[test]
+    fn test_pipe() {
+        let line = parse("kstat-dump | grep -i \"swap\" | wc -l ");
+        assert_eq!(line.argv, ["kstat-dump"]);
+        assert_eq!(
+            line.redirect,
+            Some(Redirect::Pipe(String::from("grep -i \"swap\" | wc -l")))
+        );
+
+        let line = parse("wait-until freemem > lotsfree | cat > out.txt");
+        assert_eq!(line.argv, ["wait-until", "freemem", ">", "lotsfree"]);
+        assert_eq!(
+            line.redirect,
+            Some(Redirect::Pipe(String::from("cat > out.txt")))
+        );
+
+        let line = parse("echo \"|\" x");
+        assert_eq!(line.argv, ["echo", "|", "x"]);
+        assert_eq!(
+            parse_err("kstat-dump | "),
+            "expected a command after \"|\""
+        );
+    }
+}
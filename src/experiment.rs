@@ -0,0 +1,265 @@
+//! Programmable experiments
+//!
+//! Command scripts (see the REPL's `source` command) run a fixed sequence of
+//! commands.  For experiments that need to loop or branch on stats, swappy
+//! embeds the [Rhai](https://rhai.rs) scripting language.  Experiment scripts
+//! can use these functions in addition to Rhai's built-in ones:
+//!
+//! * `reserve(size)`, `noreserve(size)`: create a mapping (like
+//!   `swap-reserve` and `swap-noreserve`), returning a `Mapping`.  Sizes may
+//!   be numbers of bytes or strings like `"1GiB"`.
+//! * `touch(m)`, `touch(m, percent)`: touch all of the pages (or the first
+//!   `percent` of them) in mapping `m`, returning the number of pages touched
+//! * `rm(m)`: remove mapping `m`
+//! * `mappings()`: returns an array of all mappings
+//! * `swap_info()`: returns swap accounting stats as an object map with
+//!   `total`, `available`, `reserved`, `allocated`, and `used` (all in bytes)
+//! * `physmem()`: returns an object map with `physmem`, `freemem`,
+//!   `availrmem`, `lotsfree`, `desfree`, and `minfree` (all in bytes)
+//! * `stat(name)`: returns the current value of any stat the monitor knows
+//!   about (e.g., `stat("pages-scanned")`)
+//! * `pressure()`: returns the memory pressure level (e.g., `"low"`)
+//! * `sleep(seconds)`: wait
+//! * `size(s)`: parses a size like `"1GiB"` into a number of bytes
+//! * `format_size(bytes)`: formats a number of bytes (e.g., `"1.0 GiB"`)
+//!
+//! `Mapping` values have `addr` (a string), `size`, `reserved`, `allocated`,
+//! and `touched` (the number of bytes touched so far) properties.  The
+//! constants `KiB`, `MiB`, `GiB`, and `TiB` are also defined.
+//!
+//! For example:
+//!
+//! ```text
+//! while swap_info().available > 5 * GiB {
+//!     reserve(GiB);
+//! }
+//! for m in mappings() {
+//!     touch(m, 50);
+//! }
+//! ```
+
+use crate::interrupt;
+use crate::jobs::JobOutcome;
+use crate::monitor::MonitorSample;
+use crate::stats::Stat;
+use crate::swappy::MappingInfo;
+use crate::swappy::Swappy;
+use crate::touch::TouchOptions;
+use anyhow::anyhow;
+use anyhow::Context;
+use bytesize::ByteSize;
+use rhai::Array;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::EvalAltResult;
+use rhai::Map;
+use rhai::Scope;
+use rhai::INT;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Runs the experiment script at `path` against the same mappings, monitor,
+/// and jobs as `swappy`
+///
+/// The script can be interrupted with SIGINT.
+pub fn run_experiment(
+    swappy: &Swappy,
+    path: &Path,
+) -> Result<(), anyhow::Error> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("reading {:?}", path))?;
+    let interrupt = Rc::new(interrupt::interruptible());
+    let mut engine = experiment_engine(swappy);
+    engine.on_progress(move |_| {
+        interrupt.interrupted().then(|| Dynamic::from("interrupted"))
+    });
+
+    let mut scope = Scope::new();
+    let units: [(&str, INT); 4] = [
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("TiB", 1 << 40),
+    ];
+    for (name, value) in units {
+        scope.push_constant(name, value);
+    }
+    engine
+        .run_with_scope(&mut scope, &source)
+        .map_err(|error| anyhow!("{}: {}", path.display(), error))
+}
+
+/// Returns an engine with swappy's functions registered (see the module
+/// documentation)
+fn experiment_engine(swappy: &Swappy) -> Engine {
+    let swappy = Rc::new(RefCell::new(swappy.clone()));
+    let mut engine = Engine::new();
+
+    engine
+        .register_type_with_name::<MappingInfo>("Mapping")
+        .register_get("addr", |m: &mut MappingInfo| format!("0x{:x}", m.addr))
+        .register_get("size", |m: &mut MappingInfo| m.size.as_u64() as INT)
+        .register_get("reserved", |m: &mut MappingInfo| m.reserved)
+        .register_get("allocated", |m: &mut MappingInfo| m.allocated)
+        .register_get("touched", |m: &mut MappingInfo| {
+            m.touched.as_u64() as INT
+        })
+        .register_fn("to_string", describe_mapping)
+        .register_fn("to_debug", describe_mapping);
+
+    let s = Rc::clone(&swappy);
+    engine.register_fn("reserve", move |size: Dynamic| {
+        let addr = s.borrow_mut().swap_reserve(size_arg(size)?);
+        find_mapping(&s.borrow(), addr.map_err(script_error)?)
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("noreserve", move |size: Dynamic| {
+        let addr = s.borrow_mut().swap_noreserve(size_arg(size)?);
+        find_mapping(&s.borrow(), addr.map_err(script_error)?)
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("touch", move |m: MappingInfo| {
+        touch(&mut s.borrow_mut(), &m, None)
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("touch", move |m: MappingInfo, percent: Dynamic| {
+        let percent = percent
+            .as_float()
+            .or_else(|_| percent.as_int().map(|p| p as f64))
+            .map_err(|t| format!("percent: expected a number, found {}", t))?;
+        touch(&mut s.borrow_mut(), &m, Some(percent))
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("rm", move |m: MappingInfo| -> ScriptResult<()> {
+        match s.borrow_mut().swap_rm(m.addr).map_err(script_error)? {
+            JobOutcome::Completed => Ok(()),
+            JobOutcome::Cancelled => Err("interrupted".into()),
+        }
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("mappings", move || -> Array {
+        s.borrow().mappings().iter().map(|m| Dynamic::from(m.info())).collect()
+    });
+
+    engine.register_fn("swap_info", || -> ScriptResult<Map> {
+        let swapinfo = Swappy::swap_info().map_err(script_error)?;
+        Ok(bytes_map([
+            ("total", swapinfo.total()),
+            ("available", swapinfo.available()),
+            ("reserved", swapinfo.reserved()),
+            ("allocated", swapinfo.allocated()),
+            ("used", swapinfo.allocated() + swapinfo.reserved()),
+        ]))
+    });
+    engine.register_fn("physmem", || -> ScriptResult<Map> {
+        let physmem = MonitorSample::fetch().map_err(script_error)?.physmem;
+        Ok(bytes_map([
+            ("physmem", physmem.physmem),
+            ("freemem", physmem.freemem),
+            ("availrmem", physmem.availrmem),
+            ("lotsfree", physmem.lotsfree),
+            ("desfree", physmem.desfree),
+            ("minfree", physmem.minfree),
+        ]))
+    });
+    engine.register_fn("stat", |name: &str| -> ScriptResult<INT> {
+        let stat = Stat::from_str(name).map_err(script_error)?;
+        let sample = MonitorSample::fetch().map_err(script_error)?;
+        let value = stat
+            .value(&sample)
+            .ok_or_else(|| format!("stat {:?} is not available", name))?;
+        Ok(value as INT)
+    });
+    let s = Rc::clone(&swappy);
+    engine.register_fn("pressure", move || -> ScriptResult<String> {
+        let pressure = s.borrow().pressure().map_err(script_error)?;
+        Ok(pressure.level.name().to_string())
+    });
+
+    engine.register_fn("sleep", |seconds: Dynamic| -> ScriptResult<()> {
+        let seconds = seconds
+            .as_float()
+            .or_else(|_| seconds.as_int().map(|s| s as f64))
+            .map_err(|t| format!("sleep: expected a number, found {}", t))?;
+        let duration = Duration::try_from_secs_f64(seconds)
+            .map_err(|e| format!("sleep: {}", e))?;
+        if interrupt::sleep(duration) {
+            return Err("interrupted".into());
+        }
+        Ok(())
+    });
+    engine.register_fn("size", |s: Dynamic| size_arg(s).map(|b| b as INT));
+    engine.register_fn("format_size", |bytes: INT| {
+        ByteSize::b(bytes.unsigned_abs()).to_string_as(true)
+    });
+
+    engine
+}
+
+fn describe_mapping(m: &mut MappingInfo) -> String {
+    format!("0x{:x} ({})", m.addr, m.size.to_string_as(true))
+}
+
+/// Converts an error from a swappy operation into a script error
+fn script_error(error: anyhow::Error) -> Box<EvalAltResult> {
+    format!("{:#}", error).into()
+}
+
+/// Interprets a size given to a script function as either a number of bytes
+/// or a string like "1GiB"
+fn size_arg(size: Dynamic) -> ScriptResult<usize> {
+    let bytes = if let Ok(bytes) = size.as_int() {
+        u64::try_from(bytes).map_err(|_| format!("invalid size: {}", bytes))?
+    } else if let Some(s) = size.clone().try_cast::<rhai::ImmutableString>() {
+        ByteSize::from_str(&s)
+            .map_err(|e| format!("invalid size {:?}: {}", s.as_str(), e))?
+            .as_u64()
+    } else {
+        return Err(format!(
+            "expected a size (a number or a string like \"1GiB\"), found {}",
+            size.type_name()
+        )
+        .into());
+    };
+    usize::try_from(bytes)
+        .map_err(|_| format!("size too large: {}", bytes).into())
+}
+
+/// Returns the mapping at `addr`
+fn find_mapping(swappy: &Swappy, addr: usize) -> ScriptResult<MappingInfo> {
+    swappy
+        .mappings()
+        .iter()
+        .map(|m| m.info())
+        .find(|m| m.addr == addr)
+        .ok_or_else(|| format!("no mapping with address 0x{:x}", addr).into())
+}
+
+fn touch(
+    swappy: &mut Swappy,
+    mapping: &MappingInfo,
+    percent: Option<f64>,
+) -> ScriptResult<INT> {
+    let options = TouchOptions { percent, ..TouchOptions::default() };
+    let report =
+        swappy.swap_touch(mapping.addr, &options).map_err(script_error)?;
+    match report.outcome {
+        JobOutcome::Completed => Ok(report.npages() as INT),
+        JobOutcome::Cancelled => Err("interrupted".into()),
+    }
+}
+
+/// Builds an object map whose values are the given sizes, in bytes
+fn bytes_map<const N: usize>(values: [(&str, ByteSize); N]) -> Map {
+    values
+        .into_iter()
+        .map(|(name, value)| {
+            (name.into(), Dynamic::from(value.as_u64() as INT))
+        })
+        .collect()
+}
@@ -0,0 +1,250 @@
+//! Latency histogram
+//!
+//! This is a simple log-linear histogram in the style of HdrHistogram: values
+//! are grouped by power of two, and each power-of-two range is split into a
+//! fixed number of linear sub-buckets.  That bounds the relative error of any
+//! reported value (to 1/8th, here) while using a small, fixed amount of memory
+//! regardless of how many values are recorded.
+
+use std::time::Duration;
+
+/// log2 of the number of sub-buckets per power of two
+const SUB_BUCKET_BITS: u32 = 3;
+/// number of sub-buckets per power of two
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// total number of buckets needed to cover all u64 values
+const NBUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Width of the widest bar printed by the `Display` impl
+const BAR_WIDTH: u64 = 40;
+
+/// Records a distribution of latencies (in nanoseconds)
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { counts: vec![0; NBUCKETS], total: 0, min: u64::MAX, max: 0 }
+    }
+
+    /// Record one observation
+    pub fn record(&mut self, latency: Duration) {
+        let nsec = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket_index(nsec)] += 1;
+        self.total += 1;
+        self.min = self.min.min(nsec);
+        self.max = self.max.max(nsec);
+    }
+
+    /// Add all of the observations in `other` to this histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (mine, theirs) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *mine += theirs;
+        }
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Returns the number of observations recorded
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the largest observation recorded
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Returns (an approximation of) the value below which `pct` percent of
+    /// observations fall
+    pub fn percentile(&self, pct: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+
+        let target = ((pct / 100.0) * (self.total as f64)).ceil().max(1.0);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if (seen as f64) >= target {
+                let (_, upper) = bucket_bounds(i);
+                let nsec = (upper - 1).clamp(self.min, self.max);
+                return Duration::from_nanos(nsec);
+            }
+        }
+
+        Duration::from_nanos(self.max)
+    }
+}
+
+impl std::fmt::Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "p50: {}  p90: {}  p99: {}  max: {}  ({} samples)\n",
+            DurationDisplay(self.percentile(50.0)),
+            DurationDisplay(self.percentile(90.0)),
+            DurationDisplay(self.percentile(99.0)),
+            DurationDisplay(self.max()),
+            self.total,
+        ))?;
+
+        // For printing, we collapse the sub-buckets so that there's one row per
+        // power of two.
+        let mut rows: Vec<(u64, u64)> = Vec::new();
+        for (i, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let (lower, _) = bucket_bounds(i);
+            let row_lower = if lower == 0 { 0 } else { 1 << lower.ilog2() };
+            match rows.last_mut() {
+                Some((last_lower, last_count)) if *last_lower == row_lower => {
+                    *last_count += count;
+                }
+                _ => rows.push((row_lower, *count)),
+            }
+        }
+
+        let max_count = rows.iter().map(|(_, c)| *c).max().unwrap_or(0);
+        f.write_fmt(format_args!(
+            "{:>9}  {:>9}  {:>10}\n",
+            "FROM", "TO", "COUNT"
+        ))?;
+        for (lower, count) in rows {
+            let upper = if lower == 0 { 1 } else { lower.saturating_mul(2) };
+            let bar = usize::try_from(count * BAR_WIDTH / max_count).unwrap();
+            f.write_fmt(format_args!(
+                "{:>9}  {:>9}  {:10} {}\n",
+                DurationDisplay(Duration::from_nanos(lower)).to_string(),
+                DurationDisplay(Duration::from_nanos(upper)).to_string(),
+                count,
+                "#".repeat(bar.max(1)),
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the index of the bucket that holds `value`
+fn bucket_index(value: u64) -> usize {
+    if value < (SUB_BUCKETS as u64) {
+        return usize::try_from(value).unwrap();
+    }
+
+    let exp = value.ilog2();
+    let sub = (value >> (exp - SUB_BUCKET_BITS)) & ((SUB_BUCKETS as u64) - 1);
+    let group = (exp - SUB_BUCKET_BITS + 1) as usize;
+    group * SUB_BUCKETS + usize::try_from(sub).unwrap()
+}
+
+/// Returns the range of values `[lower, upper)` held by bucket `index`
+fn bucket_bounds(index: usize) -> (u64, u64) {
+    let group = index / SUB_BUCKETS;
+    let sub = (index % SUB_BUCKETS) as u64;
+    if group == 0 {
+        return (sub, sub + 1);
+    }
+
+    let shift = group - 1;
+    let lower = ((SUB_BUCKETS as u64) + sub) << shift;
+    (lower, lower.saturating_add(1 << shift))
+}
+
+/// Formats a [`Duration`] compactly using an appropriate unit
+pub struct DurationDisplay(pub Duration);
+impl std::fmt::Display for DurationDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let nsec = self.0.as_nanos();
+        if nsec < 1_000 {
+            f.write_fmt(format_args!("{}ns", nsec))
+        } else if nsec < 1_000_000 {
+            f.write_fmt(format_args!("{:.1}us", (nsec as f64) / 1e3))
+        } else if nsec < 1_000_000_000 {
+            f.write_fmt(format_args!("{:.1}ms", (nsec as f64) / 1e6))
+        } else {
+            f.write_fmt(format_args!("{:.1}s", (nsec as f64) / 1e9))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bucket_bounds;
+    use super::bucket_index;
+    use super::Histogram;
+    use super::NBUCKETS;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_index() {
+        // Small values each get their own bucket.
+        for value in 0..8 {
+            assert_eq!(bucket_index(value), value as usize);
+        }
+        // After that, each power of two is split into 8 buckets.
+        assert_eq!(bucket_index(8), 8);
+        assert_eq!(bucket_index(15), 15);
+        assert_eq!(bucket_index(16), 16);
+        assert_eq!(bucket_index(17), 16);
+        assert_eq!(bucket_index(18), 17);
+        assert_eq!(bucket_index(31), 23);
+        assert_eq!(bucket_index(32), 24);
+        assert_eq!(bucket_index(u64::MAX), NBUCKETS - 1);
+
+        for value in [0, 7, 8, 9, 15, 16, 17, 1000, 1023, 1024, 1 << 40] {
+            let (lower, upper) = bucket_bounds(bucket_index(value));
+            assert!(lower <= value && value < upper, "value {}", value);
+            assert_eq!(bucket_index(lower), bucket_index(value));
+            assert_eq!(bucket_index(upper), bucket_index(value) + 1);
+        }
+        let (lower, upper) = bucket_bounds(NBUCKETS - 1);
+        assert_eq!(lower, 15 << 60);
+        assert_eq!(upper, u64::MAX);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.percentile(50.0), Duration::ZERO);
+
+        histogram.record(Duration::from_nanos(100));
+        assert_eq!(histogram.percentile(0.0), Duration::from_nanos(100));
+        assert_eq!(histogram.percentile(50.0), Duration::from_nanos(100));
+        assert_eq!(histogram.percentile(100.0), Duration::from_nanos(100));
+
+        let mut histogram = Histogram::new();
+        for nsec in 1..=100 {
+            histogram.record(Duration::from_nanos(nsec));
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.max(), Duration::from_nanos(100));
+        assert_eq!(histogram.percentile(0.0), Duration::from_nanos(1));
+        assert_eq!(histogram.percentile(5.0), Duration::from_nanos(5));
+        // 50 falls in the bucket [48, 52), and percentiles are reported as
+        // the top of the bucket.
+        assert_eq!(histogram.percentile(50.0), Duration::from_nanos(51));
+        // ... but never more than the largest value recorded.
+        assert_eq!(histogram.percentile(99.0), Duration::from_nanos(100));
+        assert_eq!(histogram.percentile(100.0), Duration::from_nanos(100));
+
+        let mut merged = Histogram::new();
+        merged.merge(&histogram);
+        merged.record(Duration::from_secs(1));
+        assert_eq!(merged.count(), 101);
+        assert_eq!(merged.percentile(100.0), Duration::from_secs(1));
+        assert_eq!(merged.percentile(50.0), Duration::from_nanos(51));
+    }
+}
@@ -0,0 +1,114 @@
+//! Samples retained by the monitor over the session
+//!
+//! Every sample that the monitor collects is also appended to a bounded
+//! in-memory history so that it can be summarized after the fact (e.g., with
+//! the REPL's `history` command), once the monitor's output has scrolled away.
+
+use crate::monitor::MonitorSample;
+use crate::stats::Stat;
+use crate::stats::StatKind;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Maximum number of samples retained (a bit under 3 hours at the default
+/// interval)
+const HISTORY_CAPACITY: usize = 10_000;
+
+/// Characters used to draw sparklines, from lowest to highest
+const SPARK_CHARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Bounded buffer of the most recent samples collected by the monitor
+pub(crate) struct History {
+    samples: VecDeque<Arc<MonitorSample>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History { samples: VecDeque::new() }
+    }
+
+    /// Appends a sample, discarding the oldest one if the history is full
+    pub fn push(&mut self, sample: Arc<MonitorSample>) {
+        if self.samples.len() >= HISTORY_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Returns the samples collected within the last `since` (or all of them,
+    /// if `since` is `None`), oldest first
+    pub fn samples(&self, since: Option<Duration>) -> Vec<Arc<MonitorSample>> {
+        self.samples
+            .iter()
+            .filter(|s| since.map(|d| s.instant.elapsed() <= d).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Summarizes the values of one stat over a series of samples
+pub struct StatSummary {
+    pub stat: Stat,
+    /// value for each sample
+    ///
+    /// For [`StatKind::Counter`] stats, this is the change since the previous
+    /// sample (so the first sample has no value).  Samples in which the stat
+    /// was unavailable are skipped.
+    pub values: Vec<u64>,
+    pub min: u64,
+    pub max: u64,
+    pub avg: f64,
+}
+
+impl StatSummary {
+    /// Summarizes `stat` over `samples`, returning `None` if there are no
+    /// values to summarize
+    pub fn new(stat: Stat, samples: &[Arc<MonitorSample>]) -> Option<Self> {
+        let values: Vec<u64> = match stat.kind() {
+            StatKind::Bytes | StatKind::Pressure => {
+                samples.iter().filter_map(|s| stat.value(s)).collect()
+            }
+            // Counters may go backwards across monitor sessions (e.g., ARC
+            // shrinks are counted from when the monitor starts).  Treat that
+            // as no change.
+            StatKind::Counter => samples
+                .windows(2)
+                .filter_map(|w| stat.delta(&w[1], &w[0]))
+                .map(|delta| u64::try_from(delta).unwrap_or(0))
+                .collect(),
+        };
+
+        let min = *values.iter().min()?;
+        let max = *values.iter().max()?;
+        let avg = values.iter().map(|v| *v as f64).sum::<f64>()
+            / (values.len() as f64);
+        Some(StatSummary { stat, values, min, max, avg })
+    }
+
+    /// Returns a sparkline of these values at most `width` characters wide
+    ///
+    /// If there are more values than that, consecutive values are averaged.
+    pub fn sparkline(&self, width: usize) -> String {
+        let nbuckets = self.values.len().min(width.max(1));
+        let range = (self.max - self.min) as f64;
+        (0..nbuckets)
+            .map(|i| {
+                let start = i * self.values.len() / nbuckets;
+                let end =
+                    ((i + 1) * self.values.len() / nbuckets).max(start + 1);
+                let bucket = &self.values[start..end];
+                let avg = bucket.iter().map(|v| *v as f64).sum::<f64>()
+                    / (bucket.len() as f64);
+                let which = if range == 0.0 {
+                    0
+                } else {
+                    let frac = (avg - (self.min as f64)) / range;
+                    ((frac * ((SPARK_CHARS.len() - 1) as f64)).round() as usize)
+                        .min(SPARK_CHARS.len() - 1)
+                };
+                SPARK_CHARS[which]
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+//! Handling for SIGINT (Ctrl-C)
+//!
+//! SIGINT never terminates swappy, since that would throw away whatever
+//! mappings the user has set up.  Instead, the handler just records that it
+//! was received.  Interruptible operations check for that periodically so
+//! that they can stop early and return to the prompt; other operations just
+//! run to completion.  Operations mark themselves interruptible by holding an
+//! [`InterruptGuard`] (see [`interruptible()`]).  Operations that already
+//! support cancellation through a [`JobProgress`] (like touches and removals)
+//! can use [`InterruptGuard::cancel_on_interrupt()`] instead of checking for
+//! SIGINT themselves.
+//!
+//! SIGINT is meant for whatever the user is waiting on, so operations running
+//! on a thread marked with [`set_background()`] (like scheduled commands) are
+//! never interrupted.
+
+use crate::jobs::JobProgress;
+use anyhow::Context;
+use std::cell::Cell;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
+
+/// how often [`InterruptGuard::cancel_on_interrupt()`] and [`sleep()`] check
+/// for SIGINT
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// number of times SIGINT has been received
+static NINTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// whether the current thread was marked with [`set_background()`]
+    static BACKGROUND: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Installs the SIGINT handler
+///
+/// This should be called once at startup.
+pub fn install() -> Result<(), anyhow::Error> {
+    // Safety: the handler only operates on an atomic, which is
+    // async-signal-safe.
+    unsafe {
+        signal_hook::low_level::register(signal_hook::consts::SIGINT, || {
+            NINTERRUPTS.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+    .context("installing SIGINT handler")?;
+    Ok(())
+}
+
+/// Marks the current thread as running in the background, so that SIGINT
+/// doesn't interrupt operations on it
+pub fn set_background() {
+    BACKGROUND.with(|background| background.set(true));
+}
+
+/// Marks the current operation interruptible until the returned guard is
+/// dropped
+///
+/// Only SIGINT received after this call interrupts the operation.
+pub fn interruptible() -> InterruptGuard {
+    InterruptGuard {
+        start: NINTERRUPTS.load(Ordering::SeqCst),
+        foreground: !BACKGROUND.with(|background| background.get()),
+    }
+}
+
+/// Sleeps for `duration`, returning early (with `true`) if interrupted with
+/// SIGINT
+pub fn sleep(duration: Duration) -> bool {
+    let interrupt = interruptible();
+    let deadline = Instant::now() + duration;
+    loop {
+        if interrupt.interrupted() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        // Wake up periodically to check for an interrupt.
+        std::thread::sleep((deadline - now).min(POLL_INTERVAL));
+    }
+}
+
+/// Represents an operation that SIGINT should interrupt
+pub struct InterruptGuard {
+    /// value of `NINTERRUPTS` when the operation started
+    start: u64,
+    /// whether the operation is running on a foreground thread
+    foreground: bool,
+}
+
+impl InterruptGuard {
+    /// Returns whether SIGINT has been received since this operation started
+    pub fn interrupted(&self) -> bool {
+        self.foreground && NINTERRUPTS.load(Ordering::SeqCst) != self.start
+    }
+
+    /// Runs `f`, cancelling `progress` (which `f` is expected to check) if
+    /// SIGINT is received before it finishes
+    pub fn cancel_on_interrupt<T>(
+        &self,
+        progress: &JobProgress,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            scope.spawn(move || loop {
+                match done_rx.recv_timeout(POLL_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if self.interrupted() {
+                            progress.cancel();
+                            return;
+                        }
+                    }
+                    // `f` has finished.
+                    _ => return,
+                }
+            });
+            let rv = f();
+            drop(done_tx);
+            rv
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::install;
+    use super::interruptible;
+    use super::set_background;
+
+    #[test]
+    fn test_interrupt() {
+        install().unwrap();
+        let guard = interruptible();
+        let background = std::thread::spawn(|| {
+            set_background();
+            interruptible()
+        })
+        .join()
+        .unwrap();
+        assert!(!guard.interrupted());
+
+        // The process survives SIGINT, even with no guard on this thread.
+        // Safety: raise() is always safe to call.
+        assert_eq!(unsafe { libc::raise(libc::SIGINT) }, 0);
+        assert!(guard.interrupted());
+        assert!(!background.interrupted());
+        // SIGINT received earlier doesn't interrupt new operations.
+        assert!(!interruptible().interrupted());
+    }
+}
@@ -0,0 +1,222 @@
+//! Background jobs
+//!
+//! Operations that take a while (like touching or removing large mappings) can
+//! be run in a worker thread so that the REPL stays responsive.  Each of these
+//! is a job.  Jobs report progress and can be cancelled through a shared
+//! [`JobProgress`].
+
+use crate::interrupt;
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use serde::Serialize;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Tracks how far along an operation is and whether it's been cancelled
+///
+/// This is shared between the thread doing the work and whoever wants to
+/// observe or cancel it.
+pub struct JobProgress {
+    done: AtomicU64,
+    total: u64,
+    cancelled: AtomicBool,
+}
+
+impl JobProgress {
+    /// Returns a new `JobProgress` for an operation covering `total` bytes
+    pub fn new(total: u64) -> JobProgress {
+        JobProgress {
+            done: AtomicU64::new(0),
+            total,
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    /// Record that `nbytes` more bytes have been processed
+    pub fn advance(&self, nbytes: u64) {
+        self.done.fetch_add(nbytes, Ordering::Relaxed);
+    }
+
+    /// Returns how many bytes have been processed so far
+    pub fn done(&self) -> ByteSize {
+        ByteSize::b(self.done.load(Ordering::Relaxed))
+    }
+
+    /// Returns the total number of bytes covered by this operation
+    pub fn total(&self) -> ByteSize {
+        ByteSize::b(self.total)
+    }
+
+    /// Ask the operation to stop at the next opportunity
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether someone has asked the operation to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Describes how an operation finished (assuming it didn't fail)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobOutcome {
+    /// the operation ran to completion
+    Completed,
+    /// the operation stopped early because it was cancelled
+    Cancelled,
+}
+
+/// Describes the state of a job, as reported by [`Jobs::list()`]
+#[derive(Debug)]
+pub enum JobState {
+    Running,
+    Done,
+    Cancelled,
+    Failed(String),
+}
+
+impl std::fmt::Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobState::Running => f.pad("running"),
+            JobState::Done => f.pad("done"),
+            JobState::Cancelled => f.pad("cancelled"),
+            JobState::Failed(_) => f.pad("failed"),
+        }
+    }
+}
+
+/// Summarizes one job, as reported by [`Jobs::list()`]
+#[derive(Debug)]
+pub struct JobStatus {
+    pub id: usize,
+    pub description: String,
+    pub done: ByteSize,
+    pub total: ByteSize,
+    pub state: JobState,
+}
+
+type JobResult = Result<JobOutcome, anyhow::Error>;
+
+struct Job {
+    id: usize,
+    description: String,
+    progress: Arc<JobProgress>,
+    thread: std::thread::JoinHandle<JobResult>,
+}
+
+/// Keeps track of background jobs
+pub(crate) struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Jobs { jobs: Vec::new(), next_id: 1 }
+    }
+
+    /// Run `func` in a new worker thread, returning the id of the new job
+    ///
+    /// `func` is given the job's [`JobProgress`], which it should update as it
+    /// goes and check for cancellation.  When the job finishes, a one-line
+    /// summary is printed.
+    pub fn spawn<F>(
+        &mut self,
+        description: String,
+        total: u64,
+        func: F,
+    ) -> usize
+    where
+        F: FnOnce(&Arc<JobProgress>) -> JobResult + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let progress = Arc::new(JobProgress::new(total));
+        let thread_progress = Arc::clone(&progress);
+        let thread_description = description.clone();
+        let thread = std::thread::spawn(move || {
+            // Jobs are stopped with "job-cancel", not Ctrl-C.
+            interrupt::set_background();
+            let result = func(&thread_progress);
+            match &result {
+                Ok(JobOutcome::Completed) => {
+                    println!("[{}] done: {}", id, thread_description)
+                }
+                Ok(JobOutcome::Cancelled) => println!(
+                    "[{}] cancelled: {} ({} of {} processed)",
+                    id,
+                    thread_description,
+                    thread_progress.done(),
+                    thread_progress.total()
+                ),
+                Err(error) => {
+                    println!(
+                        "[{}] failed: {}: {:#}",
+                        id, thread_description, error
+                    )
+                }
+            }
+            result
+        });
+
+        self.jobs.push(Job { id, description, progress, thread });
+        id
+    }
+
+    /// Returns the status of all jobs
+    ///
+    /// Jobs that have finished are reported once and then forgotten.
+    pub fn list(&mut self) -> Vec<JobStatus> {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            self.jobs.drain(..).partition(|j| j.thread.is_finished());
+        self.jobs = running;
+
+        let mut rv: Vec<JobStatus> = self
+            .jobs
+            .iter()
+            .map(|job| JobStatus {
+                id: job.id,
+                description: job.description.clone(),
+                done: job.progress.done(),
+                total: job.progress.total(),
+                state: JobState::Running,
+            })
+            .collect();
+
+        rv.extend(finished.into_iter().map(|job| {
+            let state = match job.thread.join() {
+                Ok(Ok(JobOutcome::Completed)) => JobState::Done,
+                Ok(Ok(JobOutcome::Cancelled)) => JobState::Cancelled,
+                Ok(Err(error)) => JobState::Failed(format!("{:#}", error)),
+                Err(_) => JobState::Failed(String::from("job panicked")),
+            };
+            JobStatus {
+                id: job.id,
+                description: job.description,
+                done: job.progress.done(),
+                total: job.progress.total(),
+                state,
+            }
+        }));
+
+        rv.sort_by_key(|j| j.id);
+        rv
+    }
+
+    /// Ask job `id` to stop at the next opportunity
+    pub fn cancel(&self, id: usize) -> Result<(), anyhow::Error> {
+        let job = self
+            .jobs
+            .iter()
+            .find(|j| j.id == id)
+            .ok_or_else(|| anyhow!("no job with id {}", id))?;
+        job.progress.cancel();
+        Ok(())
+    }
+}
@@ -5,6 +5,8 @@ use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use bytesize::ByteSize;
+use serde::Deserialize;
+use serde::Serialize;
 
 /// Reads kstats about physical memory using the given `kstat` handle
 pub fn kstat_read_physmem(
@@ -21,15 +23,23 @@ pub fn kstat_read_physmem(
     PhysicalMemoryStats::from_kstat(&data)
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
+/// Describes physical memory usage and the pageout thresholds
+///
+/// See the `system_pages` kstat.  Each value is serialized in bytes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PhysicalMemoryStats {
+    /// total physical memory
+    pub physmem: ByteSize,
+    /// memory not currently in use
     pub freemem: ByteSize,
-    physmem: u64,
-    availrmem: u64,
-    lotsfree: u64,
-    desfree: u64,
-    minfree: u64,
+    /// memory available to be locked or reserved by the kernel
+    pub availrmem: ByteSize,
+    /// when freemem drops below this, the pageout scanner starts
+    pub lotsfree: ByteSize,
+    /// when freemem drops below this, the system begins swapping
+    pub desfree: ByteSize,
+    /// when freemem drops below this, allocations may block
+    pub minfree: ByteSize,
 }
 
 impl PhysicalMemoryStats {
@@ -73,22 +83,227 @@ impl PhysicalMemoryStats {
             *which_value = Some(value);
         }
 
+        let pages = |value: Option<u64>, name: &str| {
+            value
+                .map(|npages| ByteSize::b(npages * (PAGE_SIZE as u64)))
+                .ok_or_else(|| anyhow!("missing stat {}", name))
+        };
+
         Ok(PhysicalMemoryStats {
-            physmem: physmem.ok_or_else(|| anyhow!("missing stat physmem"))?,
-            freemem: ByteSize::b(
-                freemem.ok_or_else(|| anyhow!("missing stat freemem"))?
-                    * (PAGE_SIZE as u64),
-            ),
-            availrmem: availrmem
-                .ok_or_else(|| anyhow!("missing stat availrmem"))?,
-            lotsfree: lotsfree
-                .ok_or_else(|| anyhow!("missing stat lotsfree"))?,
-            desfree: desfree.ok_or_else(|| anyhow!("missing stat desfree"))?,
-            minfree: minfree.ok_or_else(|| anyhow!("missing stat minfree"))?,
+            physmem: pages(physmem, "physmem")?,
+            freemem: pages(freemem, "freemem")?,
+            availrmem: pages(availrmem, "availrmem")?,
+            lotsfree: pages(lotsfree, "lotsfree")?,
+            desfree: pages(desfree, "desfree")?,
+            minfree: pages(minfree, "minfree")?,
         })
     }
 }
 
+/// Reads kstats about pageout activity using the given `kstat` handle
+///
+/// These are the `cpu:*:vm` kstats, summed over all CPUs.
+pub fn kstat_read_vm_activity(
+    kstat: &kstat_rs::Ctl,
+) -> Result<VmActivityStats, anyhow::Error> {
+    let mut rv = VmActivityStats::default();
+    let mut ncpus = 0;
+    for mut kst in kstat.filter(Some("cpu"), None, Some("vm")) {
+        let data = kstat.read(&mut kst).context("reading kstat")?;
+        let named = if let kstat_rs::Data::Named(named_stats) = &data {
+            named_stats
+        } else {
+            bail!("expected named kstat for reading vm activity");
+        };
+
+        for nst in named {
+            let which_value = match nst.name {
+                "scan" => &mut rv.pages_scanned,
+                "pgpgout" => &mut rv.pages_paged_out,
+                "anonpgout" => &mut rv.anon_pages_paged_out,
+                "dfree" => &mut rv.pages_freed,
+                _ => continue,
+            };
+            *which_value += kstat_value_u64(nst)?;
+        }
+        ncpus += 1;
+    }
+
+    if ncpus == 0 {
+        bail!("found no cpu vm kstats");
+    }
+    Ok(rv)
+}
+
+/// Describes pageout activity since boot
+///
+/// See the `cpu:*:vm` kstats.  All of these are cumulative counts of pages.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VmActivityStats {
+    /// pages examined by the pageout scanner
+    pub pages_scanned: u64,
+    /// pages paged out
+    pub pages_paged_out: u64,
+    /// anonymous pages paged out (i.e., to swap)
+    pub anon_pages_paged_out: u64,
+    /// pages freed by the pageout scanner
+    pub pages_freed: u64,
+}
+
+/// kmem cache whose reap count stands in for the system's (see
+/// [`kstat_read_kmem_reaps()`])
+const KMEM_REAP_CACHE: &str = "kmem_alloc_8";
+
+/// Reads the number of times the system has reaped the kmem caches using the
+/// given `kstat` handle
+///
+/// When memory runs low, the system reaps all of the kmem caches at once, so
+/// this is the `reap` stat of one cache that always exists.  That's far
+/// cheaper than reading the kstats of the hundreds of caches on each sample.
+/// It doesn't count reaps of individual caches (e.g., by the ARC).
+pub fn kstat_read_kmem_reaps(
+    kstat: &kstat_rs::Ctl,
+) -> Result<u64, anyhow::Error> {
+    let mut filter = kstat.filter(Some("unix"), Some(0), Some(KMEM_REAP_CACHE));
+    let mut kst = filter
+        .next()
+        .ok_or_else(|| anyhow!("found no {} kstat", KMEM_REAP_CACHE))?;
+    let data = kstat.read(&mut kst).context("reading kstat")?;
+    let named = if let kstat_rs::Data::Named(named_stats) = &data {
+        named_stats
+    } else {
+        bail!("expected named kstat for reading kmem reaps");
+    };
+
+    let nst = named
+        .iter()
+        .find(|n| n.name == "reap")
+        .ok_or_else(|| anyhow!("missing stat reap"))?;
+    kstat_value_u64(nst)
+}
+
+/// Reads kstats about the ZFS ARC using the given `kstat` handle
+///
+/// Returns `None` if ZFS is not loaded.
+pub fn kstat_read_arc(
+    kstat: &kstat_rs::Ctl,
+) -> Result<Option<ArcStats>, anyhow::Error> {
+    let mut filter = kstat.filter(Some("zfs"), Some(0), Some("arcstats"));
+    let mut kst = match filter.next() {
+        Some(kst) => kst,
+        None => return Ok(None),
+    };
+
+    let data = kstat.read(&mut kst).context("reading kstat")?;
+    let named = if let kstat_rs::Data::Named(named_stats) = &data {
+        named_stats
+    } else {
+        bail!("expected named kstat for reading arcstats");
+    };
+
+    let mut size: Option<u64> = None;
+    let mut target: Option<u64> = None;
+    for nst in named {
+        let which_value = match nst.name {
+            "size" => &mut size,
+            "c" => &mut target,
+            _ => continue,
+        };
+        *which_value = Some(kstat_value_u64(nst)?);
+    }
+
+    Ok(Some(ArcStats {
+        size: ByteSize::b(size.ok_or_else(|| anyhow!("missing stat size"))?),
+        target: ByteSize::b(target.ok_or_else(|| anyhow!("missing stat c"))?),
+    }))
+}
+
+/// Describes the size of the ZFS ARC
+///
+/// See the `zfs:0:arcstats` kstat.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArcStats {
+    /// current size of the ARC
+    pub size: ByteSize,
+    /// target size of the ARC (`c`), which drops when the ARC is asked to
+    /// shrink
+    pub target: ByteSize,
+}
+
+/// Reads kstats about swap failures caused by zone caps using the given
+/// `kstat` handle
+///
+/// These are summed over all zones.  They only count allocations and
+/// reservations that failed because a zone's memory or swap cap was reached,
+/// so they're always 0 for zones without caps.
+pub fn kstat_read_zone_swap_failures(
+    kstat: &kstat_rs::Ctl,
+) -> Result<ZoneSwapFailureStats, anyhow::Error> {
+    let mut rv = ZoneSwapFailureStats::default();
+    for mut kst in kstat.filter(Some("memory_cap"), None, None) {
+        rv.anon_alloc_failures +=
+            kstat_read_named_u64(kstat, &mut kst, "anon_alloc_fail")?;
+    }
+    for mut kst in kstat.filter(Some("caps"), None, None) {
+        if kst.ks_name.starts_with("swapresv_zone_") {
+            rv.swap_reserve_failures +=
+                kstat_read_named_u64(kstat, &mut kst, "nover")?;
+        }
+    }
+    Ok(rv)
+}
+
+/// Reads `kst` and returns the value of its named stat `name` (or 0 if it has
+/// no such stat)
+fn kstat_read_named_u64<'a>(
+    kstat: &kstat_rs::Ctl,
+    kst: &mut kstat_rs::Kstat<'a>,
+    name: &str,
+) -> Result<u64, anyhow::Error> {
+    let data = kstat.read(kst).context("reading kstat")?;
+    if let kstat_rs::Data::Named(named_stats) = &data {
+        if let Some(nst) = named_stats.iter().find(|n| n.name == name) {
+            return kstat_value_u64(nst);
+        }
+    }
+    Ok(0)
+}
+
+/// Describes allocations and reservations of swap that failed because a
+/// zone's memory or swap cap was reached, since boot
+///
+/// See the `memory_cap:*` and `caps:*:swapresv_zone_*` kstats.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ZoneSwapFailureStats {
+    /// anonymous memory allocations that failed because a zone's memory cap
+    /// was reached
+    pub anon_alloc_failures: u64,
+    /// swap reservations that failed because a zone's swap cap was exceeded
+    pub swap_reserve_failures: u64,
+}
+
+impl ZoneSwapFailureStats {
+    /// Returns how many more failures `self` has than `earlier`
+    pub fn since(
+        &self,
+        earlier: &ZoneSwapFailureStats,
+    ) -> ZoneSwapFailureStats {
+        ZoneSwapFailureStats {
+            anon_alloc_failures: self
+                .anon_alloc_failures
+                .saturating_sub(earlier.anon_alloc_failures),
+            swap_reserve_failures: self
+                .swap_reserve_failures
+                .saturating_sub(earlier.swap_reserve_failures),
+        }
+    }
+
+    /// Returns whether there were no failures at all
+    pub fn is_zero(&self) -> bool {
+        self.anon_alloc_failures == 0 && self.swap_reserve_failures == 0
+    }
+}
+
 /// Given a named kstat, return the u64 value (if any)
 ///
 /// Returns an error if the value is not a u64.
@@ -1,8 +1,29 @@
+pub mod accounting;
+pub mod alert;
 pub mod bytesize_display;
+pub mod command_line;
+pub mod experiment;
+pub mod histogram;
+pub mod history;
+pub mod interrupt;
+pub mod jobs;
+pub mod memstat;
+pub mod monitor;
+pub mod monitor_output;
+pub mod output;
+pub mod pressure;
+pub mod repl;
+pub mod residency;
+pub mod schedule;
+pub mod script;
+pub mod shell;
+pub mod snapshot;
+pub mod stats;
 pub mod swappy;
+pub mod touch;
 
 mod kstat;
-mod monitor;
 mod swap;
+mod throttle;
 
 const PAGE_SIZE: usize = 4096;
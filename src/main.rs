@@ -12,70 +12,788 @@
 //     - same file management as ARC; manage an mmap mapping size and read it?
 //   - hoover up memory for kmem (socket buffers?)
 // - play around with some real examples to validate how I think this works
-// - print out more kstats:
-//   - swap allocation failures
-//   - memory values: availrmem, freemem, etc.
-//   - pageout activity?
 
 use anyhow::anyhow;
 use anyhow::Context;
+use bytesize::ByteSize;
 use reedline_repl_rs::clap::{Arg, ArgMatches, Command};
-use reedline_repl_rs::Repl;
+use reedline_repl_rs::paint_yellow_bold;
+use reedline_repl_rs::reedline::Signal;
 use std::fmt::Write;
 use std::str::FromStr;
+use swappy::accounting::AccountingSnapshot;
+use swappy::alert::AlertAction;
+use swappy::alert::Condition;
 use swappy::bytesize_display::ByteSizeDisplayGiB;
+use swappy::bytesize_display::ByteSizeDisplayIn;
+use swappy::bytesize_display::Units;
+use swappy::command_line::CommandLine;
+use swappy::command_line::Redirect;
+use swappy::experiment::run_experiment;
+use swappy::history::StatSummary;
+use swappy::interrupt;
+use swappy::jobs::JobOutcome;
+use swappy::jobs::JobState;
+use swappy::memstat::parse_memstat;
+use swappy::monitor::MonitorSample;
+use swappy::monitor_output::MonitorOutput;
+use swappy::output::format_time_of_day;
+use swappy::output::OutputFormat;
+use swappy::pressure::PressureLevel;
+use swappy::repl;
+use swappy::repl::SwappyPrompt;
+use swappy::script::Expectation;
+use swappy::shell;
+use swappy::shell::HELPER_ARG;
+use swappy::snapshot::Snapshot;
+use swappy::stats::stat_names;
+use swappy::stats::Stat;
+use swappy::stats::StatKind;
 use swappy::swappy::Swappy;
+use swappy::touch::TouchOptions;
 
-fn main() -> reedline_repl_rs::Result<()> {
-    let swappy = Swappy::new();
-    let mut repl = Repl::new(swappy)
-        .with_name("swappy")
-        .with_description("mess around with swap and physical memory")
-        .with_partial_completions(false)
-        .with_command(
+/// Description of swappy shown by `--help` and the REPL's `help` command
+const DESCRIPTION: &str = "mess around with swap and physical memory";
+
+fn main() -> Result<(), anyhow::Error> {
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == HELPER_ARG) {
+        shell::helper_main();
+    }
+
+    if let Err(error) = interrupt::install() {
+        eprintln!("warning: {:#}", error);
+    }
+
+    let cli_args = Command::new("swappy")
+        .about(DESCRIPTION)
+        .after_help(
+            "With no subcommand, swappy starts an interactive shell.  With a \
+            subcommand, swappy runs that one command and exits.",
+        )
+        .arg(format_arg().long("format").takes_value(true).global(true))
+        .subcommands(
+            commands().into_iter().map(|(command, _)| command).filter(
+                |command| ONE_SHOT_COMMANDS.contains(&command.get_name()),
+            ),
+        )
+        .get_matches();
+
+    let mut swappy = Swappy::new();
+    // Only the REPL and scripts run command lines, which may pipe output to a
+    // shell command.
+    if matches!(cli_args.subcommand_name(), None | Some("source")) {
+        if let Err(error) = swappy.start_shell_helper() {
+            eprintln!("warning: {:#}", error);
+        }
+    }
+    if let Some(format_str) = cli_args.get_one::<String>("format") {
+        // clap has already validated the value.
+        swappy.set_output_format(OutputFormat::from_str(format_str).unwrap());
+    }
+
+    if let Some((name, args)) = cli_args.subcommand() {
+        // clap only accepts subcommands that we gave it above (and reports
+        // aliases by their command's name).
+        let (_, callback) = commands()
+            .into_iter()
+            .find(|(command, _)| command.get_name() == name)
+            .unwrap();
+        match callback(args.clone(), &mut swappy) {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => (),
+            Err(error) => {
+                eprintln!("swappy: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    run_repl(swappy)
+}
+
+/// Reads and runs commands until end-of-input (Ctrl-D)
+fn run_repl(mut swappy: Swappy) -> Result<(), anyhow::Error> {
+    let commands: Vec<_> =
+        commands().into_iter().map(|(command, _)| command).collect();
+    let mut line_editor = repl::line_editor(&commands);
+    let prompt = SwappyPrompt::new("swappy");
+    loop {
+        match line_editor.read_line(&prompt).context("reading command")? {
+            Signal::Success(line) => {
+                match run_command_line(&mut swappy, &line) {
+                    Ok(Some(output)) => println!("{}", output),
+                    Ok(None) => (),
+                    Err(error) => eprintln!("{}", error),
+                }
+                after_command(&mut swappy);
+            }
+            Signal::CtrlC => (),
+            Signal::CtrlD => return Ok(()),
+        }
+    }
+}
+
+/// Signature of the functions that implement each command
+type CommandFn =
+    fn(ArgMatches, &mut Swappy) -> Result<Option<String>, SwappyError>;
+
+/// Commands that can be run as one-shot subcommands (e.g., `swappy swap-info`)
+///
+/// The rest only make sense in a session that lasts beyond one command: they
+/// operate on mappings, settings, or monitor state that would be lost as soon
+/// as swappy exits, or they schedule or repeat other commands.  They can still
+/// be run from the command line in a script (`swappy run-script FILE`).
+const ONE_SHOT_COMMANDS: &[&str] = &[
+    "source",
+    "memstat",
+    "swap-info",
+    "pressure",
+    "monitor",
+    "snapshot",
+    "experiment",
+    "kstat-dump",
+];
+
+/// Returns the definition of each command, along with the function that
+/// implements it
+///
+/// These are all available in the REPL (and in scripts), and those in
+/// [`ONE_SHOT_COMMANDS`] are also available as one-shot subcommands.
+fn commands() -> Vec<(Command<'static>, CommandFn)> {
+    vec![
+        (
+            Command::new("set")
+                .arg(
+                    Arg::new("setting")
+                        .possible_value("format")
+                        .requires("value"),
+                )
+                .arg(format_arg().id("value"))
+                .about("Show or change settings (e.g., \"set format json\")"),
+            cmd_set,
+        ),
+        (
+            Command::new("source")
+                .alias("run-script")
+                .arg(Arg::new("file").required(true))
+                .about("Run the commands and directives in a script file"),
+            cmd_source,
+        ),
+        (
+            Command::new("experiment")
+                .arg(Arg::new("file").required(true))
+                .about("Run an experiment script written in Rhai"),
+            cmd_experiment,
+        ),
+        (
             Command::new("memstat").about("Show physical memory usage"),
             cmd_memstat,
-        )
-        .with_command(
+        ),
+        (
             Command::new("swap-info").about("Show swap accounting information"),
             cmd_swap_info,
-        )
-        .with_command(
+        ),
+        (
+            Command::new("pressure").about(
+                "Classify memory pressure based on freemem and the pageout \
+                thresholds",
+            ),
+            cmd_pressure,
+        ),
+        (
             Command::new("swap-mappings")
                 .about("Show mappings created by swappy"),
             cmd_swap_mappings,
-        )
-        .with_command(
+        ),
+        (
             Command::new("swap-reserve")
                 .arg(Arg::new("size").required(true))
+                .arg(delta_only_arg())
                 .about("Create a new swap mapping"),
             cmd_swap_reserve,
-        )
-        .with_command(
+        ),
+        (
             Command::new("swap-noreserve")
                 .arg(Arg::new("size").required(true))
+                .arg(delta_only_arg())
                 .about("Create a new swap mapping with NORESERVE"),
             cmd_swap_noreserve,
-        )
-        .with_command(
+        ),
+        (
             Command::new("swap-rm")
                 .arg(Arg::new("addr").required(true))
+                .arg(delta_only_arg())
+                .arg(background_arg())
                 .about("Remove a swap mapping"),
             cmd_swap_rm,
-        )
-        .with_command(
+        ),
+        (
             Command::new("swap-touch")
                 .arg(Arg::new("addr").required(true))
+                .arg(delta_only_arg())
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .takes_value(true)
+                        .value_name("BYTES_PER_SEC")
+                        .help("limit how fast pages are touched (e.g., 1gib)"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("split the mapping across N threads"),
+                )
+                .arg(
+                    Arg::new("latency")
+                        .long("latency")
+                        .help("time page writes and print a latency histogram"),
+                )
+                .arg(
+                    Arg::new("latency-sample")
+                        .long("latency-sample")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help(
+                            "time only every Nth page write (implies \
+                            --latency)",
+                        ),
+                )
+                .arg(
+                    Arg::new("latency-file")
+                        .long("latency-file")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help(
+                            "save latency samples as CSV (implies --latency)",
+                        ),
+                )
+                .arg(background_arg())
                 .about("Touch pages in a swap mapping to allocate them"),
             cmd_swap_touch,
-        )
-        .with_command(
-            Command::new("kstat-dump")
-                .about("Dump various kstats of potential interest"),
+        ),
+        (
+            Command::new("swap-residency")
+                .arg(Arg::new("addr"))
+                .about("Show how much of each mapping is resident in memory"),
+            cmd_swap_residency,
+        ),
+        (
+            Command::new("monitor-output")
+                .arg(Arg::new("output").multiple_values(true).help(
+                    "\"terminal\", \"csv=FILE\", \"jsonl=FILE\", or \"none\"",
+                ))
+                .about("Show or set where the monitor sends stats"),
+            cmd_monitor_output,
+        ),
+        (
+            Command::new("monitor-config")
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .help("time between samples (e.g., 1s, 250ms)"),
+                )
+                .arg(
+                    Arg::new("columns")
+                        .long("columns")
+                        .takes_value(true)
+                        .value_name("STAT,...")
+                        .help(
+                            "comma-separated list of stats to print (counters \
+                            are printed as the change since the last sample; \
+                            zone-* stats count failures caused by zone caps)",
+                        ),
+                )
+                .arg(
+                    Arg::new("units")
+                        .long("units")
+                        .takes_value(true)
+                        .value_name("UNITS")
+                        .help(
+                            "units for printed values (b, kib, mib, gib, \
+                            pages)",
+                        ),
+                )
+                .arg(
+                    Arg::new("header-every")
+                        .long("header-every")
+                        .takes_value(true)
+                        .value_name("N")
+                        .help("reprint the header every N rows (0 to disable)"),
+                )
+                .arg(
+                    Arg::new("rates")
+                        .long("rates")
+                        .takes_value(true)
+                        .value_name("STAT,...")
+                        .help(
+                            "comma-separated list of stats whose per-second \
+                            rate of change to print (or \"none\")",
+                        ),
+                )
+                .arg(
+                    Arg::new("rate-units")
+                        .long("rate-units")
+                        .takes_value(true)
+                        .value_name("UNITS")
+                        .help(
+                            "units per second for printed rates (b, kib, mib, \
+                            gib, pages)",
+                        ),
+                )
+                .arg(
+                    Arg::new("timestamps")
+                        .long("timestamps")
+                        .takes_value(true)
+                        .possible_values(["on", "off"])
+                        .help("print the time of each sample"),
+                )
+                .about("Show or change what the monitor collects and prints"),
+            cmd_monitor_config,
+        ),
+        (monitor_command(), cmd_monitor),
+        (
+            Command::new("history")
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .help(
+                            "only include samples from the last DURATION \
+                            (e.g., 5m)",
+                        ),
+                )
+                .arg(
+                    Arg::new("column")
+                        .long("column")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .value_name("STAT")
+                        .help(
+                            "stat to summarize (default: the monitor's \
+                            columns)",
+                        ),
+                )
+                .about(
+                    "Summarize stats collected by the monitor during this \
+                    session",
+                ),
+            cmd_history,
+        ),
+        (
+            Command::new("alert")
+                .arg(Arg::new("condition").multiple_values(true).help(
+                    "condition like \"freemem < desfree\" or \
+                            \"swap-available < 1GiB\"",
+                ))
+                .arg(
+                    Arg::new("stop-touches").long("stop-touches").help(
+                        "stop any touches in progress when the alert fires",
+                    ),
+                )
+                .about(
+                    "Show alerts or add one for the monitor to check on each \
+                    sample",
+                ),
+            cmd_alert,
+        ),
+        (
+            Command::new("wait-until")
+                .arg(
+                    Arg::new("condition")
+                        .required(true)
+                        .multiple_values(true)
+                        .help(
+                            "condition like \"freemem > lotsfree\" or \
+                            \"swap-available > 10GiB\"",
+                        ),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .help(
+                            "fail if the condition doesn't hold within \
+                            DURATION (e.g., 30s)",
+                        ),
+                )
+                .about(
+                    "Wait (with the monitor running) until a condition on the \
+                    stats holds",
+                ),
+            cmd_wait_until,
+        ),
+        (
+            Command::new("alert-rm")
+                .arg(Arg::new("id").required(true))
+                .about("Remove an alert"),
+            cmd_alert_rm,
+        ),
+        (
+            Command::new("snapshot")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("save")
+                        .arg(Arg::new("name").required(true))
+                        .about("Save the current memory state under NAME"),
+                )
+                .subcommand(
+                    Command::new("diff")
+                        .arg(Arg::new("a").required(true))
+                        .arg(Arg::new("b").required(true))
+                        .about("Show the differences between two snapshots"),
+                )
+                .subcommand(Command::new("list").about("Show saved snapshots"))
+                .about(
+                    "Save and compare snapshots of the system's memory state",
+                ),
+            cmd_snapshot,
+        ),
+        (
+            scheduled_command("at", "DELAY").about(
+                "Run a command once after DELAY (e.g., \"at 30s \
+                    swap-info\")",
+            ),
+            cmd_at,
+        ),
+        (
+            scheduled_command("every", "INTERVAL").about(
+                "Run a command every INTERVAL (e.g., \"every 10s \
+                    swap-info\")",
+            ),
+            cmd_every,
+        ),
+        (
+            Command::new("watch")
+                .trailing_var_arg(true)
+                .arg(
+                    Arg::new("interval")
+                        .short('n')
+                        .long("interval")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .help("time between refreshes (default: 2s)"),
+                )
+                .arg(
+                    Arg::new("command")
+                        .required(true)
+                        .multiple_values(true)
+                        .value_name("COMMAND")
+                        .help("command to run, with its arguments"),
+                )
+                .about(
+                    "Re-run a command periodically until Ctrl-C, highlighting \
+                    changes",
+                ),
+            cmd_watch,
+        ),
+        (
+            Command::new("schedule")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list").about(
+                        "Show commands scheduled with \"at\" and \"every\"",
+                    ),
+                )
+                .subcommand(
+                    Command::new("cancel")
+                        .arg(Arg::new("id").required(true))
+                        .about("Cancel a scheduled command"),
+                )
+                .about("Show or cancel scheduled commands"),
+            cmd_schedule,
+        ),
+        (Command::new("jobs").about("Show background jobs"), cmd_jobs),
+        (
+            Command::new("job-cancel")
+                .arg(Arg::new("id").required(true))
+                .about("Stop a background job"),
+            cmd_job_cancel,
+        ),
+        (
+            Command::new("kstat-dump").about(
+                "Dump various kstats of potential interest (swap failures \
+                are only those caused by zone caps)",
+            ),
             cmd_kstat_dump,
-        );
+        ),
+    ]
+}
+
+/// Runs one command line (as it would be typed at the REPL) and returns its
+/// output
+///
+/// If the line redirects the output to a file or pipes it to a shell command
+/// (see [`CommandLine`]), that's done here and there's no output to return.
+fn run_command_line(
+    swappy: &mut Swappy,
+    line: &str,
+) -> Result<Option<String>, SwappyError> {
+    let command_line = CommandLine::parse(line, |name| {
+        commands().iter().any(|(command, _)| {
+            command.get_name() == name
+                && command
+                    .get_arguments()
+                    .any(|arg| arg.get_id() == "condition")
+        })
+    })?;
+    let output = run_command(swappy, &command_line.argv)?;
+    let redirect = match command_line.redirect {
+        None => return Ok(output),
+        Some(redirect) => redirect,
+    };
+
+    let output = output.map(|output| output + "\n").unwrap_or_default();
+    match redirect {
+        Redirect::File { path, append } => {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(&path)
+                .with_context(|| format!("opening {:?}", path))?;
+            std::io::Write::write_all(&mut file, output.as_bytes())
+                .with_context(|| format!("writing {:?}", path))?;
+        }
+        Redirect::Pipe(command) => {
+            let status = swappy.run_shell(&command, output.as_bytes())?;
+            if status > 0 {
+                let msg =
+                    format!("{:?} exited with status {}", command, status);
+                return Err(anyhow!(msg).into());
+            } else if status < 0 {
+                let msg =
+                    format!("{:?} was killed by signal {}", command, -status);
+                return Err(anyhow!(msg).into());
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Runs one command, given as its name followed by its arguments, and returns
+/// its output
+fn run_command(
+    swappy: &mut Swappy,
+    argv: &[String],
+) -> Result<Option<String>, SwappyError> {
+    match argv.first().map(|name| name.as_str()) {
+        None => return Ok(None),
+        Some("help") => {
+            let commands: Vec<_> =
+                commands().into_iter().map(|(command, _)| command).collect();
+            let help =
+                repl::help("swappy", DESCRIPTION, &commands, &argv[1..])?;
+            return Ok(Some(help));
+        }
+        Some(_) => (),
+    }
+    let (callback, args) = parse_command(argv)?;
+    callback(args, swappy)
+}
+
+/// Finds the command named by `argv[0]` and parses its arguments
+fn parse_command(
+    argv: &[String],
+) -> Result<(CommandFn, ArgMatches), anyhow::Error> {
+    let name = argv.first().ok_or_else(|| anyhow!("expected a command"))?;
+    let (command, callback) = commands()
+        .into_iter()
+        .find(|(command, _)| command.get_name() == name)
+        .ok_or_else(|| anyhow!("unknown command: {:?}", name))?;
+    let args = command
+        .try_get_matches_from(argv)
+        .map_err(|error| anyhow!("{}", error.to_string().trim_end()))?;
+    Ok((callback, args))
+}
+
+/// Runs each line of the file at `path`, stopping at the first one that fails
+///
+/// Each line is either a command or one of the directives handled by
+/// [`run_script_line()`].  Blank lines and lines starting with "#" are ignored.
+/// SIGINT interrupts the current line (if it's interruptible) and stops the
+/// script.
+fn run_script(swappy: &mut Swappy, path: &str) -> Result<(), SwappyError> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("reading {:?}", path))?;
+    let interrupt = interrupt::interruptible();
+    let mut baseline = None;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let output =
+            run_script_line(swappy, line, &mut baseline).map_err(|error| {
+                anyhow!("{}, line {}: {:?}: {}", path, i + 1, line, error)
+            })?;
+        if let Some(output) = output {
+            println!("{}", output);
+        }
+        if interrupt.interrupted() {
+            return Err(SwappyError(anyhow!(
+                "{}, line {}: interrupted",
+                path,
+                i + 1
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Runs one line of a script
+///
+/// Besides commands, scripts support these directives:
+///
+/// * `sleep DURATION`: wait (e.g., `sleep 5s`)
+/// * `echo TEXT`: print TEXT
+/// * `expect EXPECTATION`: fail unless a stat has the expected value (see
+///   [`Expectation`])
+///
+/// `baseline` is updated with a sample taken just before each command so that
+/// `expect ... delta` can check how the command changed a stat.
+fn run_script_line(
+    swappy: &mut Swappy,
+    line: &str,
+    baseline: &mut Option<MonitorSample>,
+) -> Result<Option<String>, SwappyError> {
+    let (word, rest) = line
+        .split_once(char::is_whitespace)
+        .map(|(word, rest)| (word, rest.trim()))
+        .unwrap_or((line, ""));
+    match word {
+        "sleep" => {
+            let duration = parse_duration(rest).context("parsing duration")?;
+            if interrupt::sleep(duration) {
+                return Err(SwappyError(anyhow!("interrupted")));
+            }
+            Ok(None)
+        }
+        "echo" => Ok(Some(rest.to_string())),
+        "expect" => {
+            let expectation = Expectation::from_str(rest)?;
+            let sample = swappy.kstat_read()?;
+            expectation.check(&sample, baseline.as_ref())?;
+            Ok(None)
+        }
+        _ => {
+            // Commands that don't need kstats shouldn't fail just because
+            // they're unavailable.  In that case, a later `expect ... delta`
+            // will report that there's no baseline.
+            *baseline = swappy.kstat_read().ok();
+            run_command_line(swappy, line)
+        }
+    }
+}
+
+fn monitor_command() -> Command<'static> {
+    Command::new("monitor")
+        .arg(
+            Arg::new("count")
+                .long("count")
+                .takes_value(true)
+                .value_name("N")
+                .help("stop after N samples (default: run until Ctrl-C)"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .takes_value(true)
+                .value_name("DURATION")
+                .help("time between samples (default: see monitor-config)"),
+        )
+        .about("Print monitor stats without doing anything else")
+}
+
+/// Returns the definition of a command that schedules another one ("at" or
+/// "every")
+fn scheduled_command(
+    name: &'static str,
+    when: &'static str,
+) -> Command<'static> {
+    Command::new(name)
+        .trailing_var_arg(true)
+        .arg(Arg::new("when").required(true).value_name(when))
+        .arg(
+            Arg::new("command")
+                .required(true)
+                .multiple_values(true)
+                .value_name("COMMAND")
+                .help("command to run, with its arguments"),
+        )
+}
+
+/// Returns the argument used to choose the output format
+fn format_arg() -> Arg<'static> {
+    Arg::new("format")
+        .value_name("FORMAT")
+        .possible_values(["text", "json"])
+        .help("format for command output")
+}
+
+/// Returns the trailing "&" argument used to run a command in the background
+fn background_arg() -> Arg<'static> {
+    Arg::new("background")
+        .possible_value("&")
+        .help("run in the background (see \"jobs\")")
+}
+
+/// Returns the "--delta-only" flag for commands that report how they changed
+/// the accounting stats
+fn delta_only_arg() -> Arg<'static> {
+    Arg::new("delta-only")
+        .long("delta-only")
+        .help("print only the change in accounting stats")
+}
+
+/// Describes how the accounting stats have changed since `before`
+///
+/// If `delta_only` is set, this is a one-line summary.  Otherwise, it's a table
+/// of the values before and after.
+fn do_print_accounting_delta(
+    before: &AccountingSnapshot,
+    delta_only: bool,
+) -> Result<String, anyhow::Error> {
+    let after = AccountingSnapshot::fetch()?;
+    let delta = before.delta(&after);
+    if delta_only {
+        Ok(delta.summary())
+    } else {
+        Ok(delta.to_string())
+    }
+}
 
-    repl.run()
+/// Describes how the accounting stats have changed since `before`, as JSON
+fn accounting_json(
+    before: &AccountingSnapshot,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let after = AccountingSnapshot::fetch()?;
+    Ok(serde_json::json!({ "before": before, "after": after }))
+}
+
+/// Returns the output of a command in JSON mode
+fn json_output(
+    value: serde_json::Value,
+) -> Result<Option<String>, SwappyError> {
+    Ok(Some(value.to_string()))
+}
+
+/// Returns the output of a command that started a background job
+fn background_output(
+    swappy: &Swappy,
+    id: usize,
+) -> Result<Option<String>, SwappyError> {
+    match swappy.output_format() {
+        OutputFormat::Text => Ok(Some(format!("[{}] started", id))),
+        OutputFormat::Json => json_output(serde_json::json!({ "job": id })),
+    }
 }
 
 #[derive(Debug)]
@@ -87,37 +805,142 @@ impl std::fmt::Display for SwappyError {
     }
 }
 
-impl From<reedline_repl_rs::Error> for SwappyError {
-    fn from(error: reedline_repl_rs::Error) -> Self {
-        SwappyError(anyhow!("REPL error: {:#}", error))
-    }
-}
-
 impl From<anyhow::Error> for SwappyError {
     fn from(error: anyhow::Error) -> Self {
         SwappyError(error)
     }
 }
 
+fn cmd_set(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    if let Some(value) = args.get_one::<String>("value") {
+        swappy.set_output_format(OutputFormat::from_str(value)?);
+        return Ok(None);
+    }
+    match swappy.output_format() {
+        OutputFormat::Text => {
+            Ok(Some(format!("format: {}", swappy.output_format())))
+        }
+        OutputFormat::Json => json_output(
+            serde_json::json!({ "format": swappy.output_format().to_string() }),
+        ),
+    }
+}
+
+fn cmd_source(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    // clap has already checked that the argument was provided.
+    run_script(swappy, args.get_one::<String>("file").unwrap())?;
+    Ok(None)
+}
+
+fn cmd_experiment(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    // clap has already checked that the argument was provided.
+    let path = args.get_one::<String>("file").unwrap();
+    run_experiment(swappy, std::path::Path::new(path))?;
+    Ok(None)
+}
+
 fn cmd_memstat(
     _args: ArgMatches,
-    _swappy: &mut Swappy,
+    swappy: &mut Swappy,
 ) -> Result<Option<String>, SwappyError> {
-    Ok(Some(Swappy::memstat().expect("memstat")))
+    let output = Swappy::memstat()?;
+    match swappy.output_format() {
+        OutputFormat::Text => Ok(Some(output)),
+        OutputFormat::Json => json_output(
+            serde_json::json!({ "memstat": parse_memstat(&output) }),
+        ),
+    }
 }
 
 fn cmd_swap_info(
     _args: ArgMatches,
-    _swappy: &mut Swappy,
+    swappy: &mut Swappy,
 ) -> Result<Option<String>, SwappyError> {
     let swapinfo = Swappy::swap_info()?;
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!(swapinfo));
+    }
     Ok(Some(swapinfo.display().to_string()))
 }
 
+fn cmd_pressure(
+    _args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let pressure = swappy.pressure()?;
+    if swappy.output_format() == OutputFormat::Json {
+        let thresholds: Vec<_> = pressure
+            .thresholds()
+            .iter()
+            .map(|(name, value, level)| {
+                serde_json::json!({
+                    "name": name,
+                    "bytes": value,
+                    "enters": level.name(),
+                })
+            })
+            .collect();
+        return json_output(serde_json::json!({
+            "level": pressure.level.name(),
+            "freemem": pressure.freemem,
+            "thresholds": thresholds,
+        }));
+    }
+
+    let mut s = String::new();
+    writeln!(s, "MEMORY PRESSURE: {}", pressure.level).unwrap();
+    writeln!(s, "freemem: {} bytes", pressure.freemem.as_u64()).unwrap();
+    writeln!(s).unwrap();
+    writeln!(
+        s,
+        "{:9}  {:>14}  {:9}  {:>20}",
+        "THRESHOLD", "BYTES", "ENTERS", "FREEMEM - THRESHOLD"
+    )
+    .unwrap();
+    for (name, value, level) in pressure.thresholds() {
+        writeln!(
+            s,
+            "{:9}  {:>14}  {:9}  {:>20}",
+            name,
+            value.as_u64(),
+            level,
+            (pressure.freemem.as_u64() as i64) - (value.as_u64() as i64),
+        )
+        .unwrap();
+    }
+    writeln!(s).unwrap();
+    match pressure.next_threshold() {
+        Some((name, distance)) => write!(
+            s,
+            "freemem is {} bytes ({}) above {}",
+            distance.as_u64(),
+            distance,
+            name
+        )
+        .unwrap(),
+        None => write!(s, "freemem is at or below minfree").unwrap(),
+    }
+    Ok(Some(s))
+}
+
 fn cmd_swap_mappings(
     _args: ArgMatches,
     swappy: &mut Swappy,
 ) -> Result<Option<String>, SwappyError> {
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(
+            serde_json::json!({ "mappings": swappy.mappings() }),
+        );
+    }
     Ok(Some(do_print_swap_mappings(swappy)))
 }
 
@@ -130,12 +953,19 @@ fn do_print_swap_mappings(swappy: &Swappy) -> String {
         let size = m.size();
         writeln!(
             s,
-            "{:16p}  {:11}  {:10} {:9} {}",
+            "{:16p}  {:11}  {:10} {:9} {:9} {}",
             m.addr,
             size.as_u64(),
             ByteSizeDisplayGiB(size),
             if m.reserved { "" } else { "NORESERVE" },
-            if m.allocated { "ALLOCATED" } else { "" },
+            if m.allocated() {
+                "ALLOCATED"
+            } else if m.touched().as_u64() > 0 {
+                "PARTIAL"
+            } else {
+                ""
+            },
+            if m.busy() { "BUSY" } else { "" },
         )
         .unwrap();
     }
@@ -163,26 +993,58 @@ fn do_swap_create_mapping(
 ) -> Result<Option<String>, SwappyError> {
     let size_str: &String =
         args.get_one("size").context("\"size\" argument")?;
-    let bytes = bytesize::ByteSize::from_str(size_str)
-        .map_err(|e| anyhow!("parsing size: {}", e))?;
+    let bytes = parse_bytesize(size_str).context("parsing size")?;
     let bytes_u64 = bytes.as_u64();
     let bytes_usize = usize::try_from(bytes_u64)
         .map_err(|e| anyhow!("value too large: {}", e))?;
+    let delta_only = args.contains_id("delta-only");
+    let before = AccountingSnapshot::fetch()?;
     let addr = if reserved {
         swappy.swap_reserve(bytes_usize)?
     } else {
         swappy.swap_noreserve(bytes_usize)?
     };
 
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!({
+            "addr": format!("0x{:x}", addr),
+            "accounting": accounting_json(&before)?,
+        }));
+    }
+
     let mut s = String::new();
     write!(s, "new mapping: 0x{:x}\n\n", addr).unwrap();
-    let swapinfo = Swappy::swap_info()?;
-    write!(s, "{}", swapinfo.display()).unwrap();
-    s.push_str("\n\n");
-    s.push_str(&do_print_swap_mappings(swappy));
+    s.push_str(&do_print_accounting_delta(&before, delta_only)?);
+    if !delta_only {
+        s.push('\n');
+        s.push_str(&do_print_swap_mappings(swappy));
+    }
     Ok(Some(s))
 }
 
+fn parse_bytesize(s: &str) -> Result<bytesize::ByteSize, anyhow::Error> {
+    bytesize::ByteSize::from_str(s).map_err(|e| anyhow!("{}", e))
+}
+
+/// Parses a duration like "500ms", "2s", "5m", or "1h" (a bare number is
+/// interpreted as seconds)
+fn parse_duration(s: &str) -> Result<std::time::Duration, anyhow::Error> {
+    let (number, scale) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60.0)
+    } else if let Some(n) = s.strip_suffix('h') {
+        (n, 3600.0)
+    } else {
+        (s, 1.0)
+    };
+    let value = f64::from_str(number).map_err(|e| anyhow!("{:?}: {}", s, e))?;
+    std::time::Duration::try_from_secs_f64(value * scale)
+        .map_err(|e| anyhow!("{:?}: {}", s, e))
+}
+
 fn cmd_swap_rm(
     args: ArgMatches,
     swappy: &mut Swappy,
@@ -192,10 +1054,42 @@ fn cmd_swap_rm(
     let addr_usize: usize = parse_int::parse(addr_str)
         .map_err(|e| anyhow!("parsing addr: {}", e))?;
 
-    swappy.swap_rm(addr_usize)?;
+    if args.contains_id("background") {
+        let id = swappy.swap_rm_background(addr_usize)?;
+        return background_output(swappy, id);
+    }
 
-    let swapinfo = Swappy::swap_info()?;
-    Ok(Some(swapinfo.display().to_string()))
+    let before = AccountingSnapshot::fetch()?;
+    let outcome = swappy.swap_rm(addr_usize)?;
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!({
+            "addr": format!("0x{:x}", addr_usize),
+            "outcome": outcome,
+            "accounting": accounting_json(&before)?,
+        }));
+    }
+
+    let mut s = String::new();
+    if outcome == JobOutcome::Cancelled {
+        // The part that wasn't removed is still there, with a smaller size.
+        if let Some(mapping) =
+            swappy.mappings().iter().find(|m| m.addr as usize == addr_usize)
+        {
+            writeln!(
+                s,
+                "interrupted: mapping 0x{:x} was only partly removed ({} \
+                remains)",
+                addr_usize,
+                mapping.size().to_string_as(true)
+            )
+            .unwrap();
+        }
+    }
+    s.push_str(&do_print_accounting_delta(
+        &before,
+        args.contains_id("delta-only"),
+    )?);
+    Ok(Some(s))
 }
 
 fn cmd_swap_touch(
@@ -207,22 +1101,976 @@ fn cmd_swap_touch(
     let addr_usize: usize = parse_int::parse(addr_str)
         .map_err(|e| anyhow!("parsing addr: {}", e))?;
 
+    let rate = args
+        .get_one::<String>("rate")
+        .map(|rate_str| parse_bytesize(rate_str).context("parsing rate"))
+        .transpose()?;
+    let nthreads = args
+        .get_one::<String>("threads")
+        .map(|n| parse_int::parse::<usize>(n))
+        .transpose()
+        .map_err(|e| anyhow!("parsing threads: {}", e))?
+        .unwrap_or(1);
+    let latency_file =
+        args.get_one::<String>("latency-file").map(std::path::PathBuf::from);
+    let latency_sample = args
+        .get_one::<String>("latency-sample")
+        .map(|n| parse_int::parse::<usize>(n))
+        .transpose()
+        .map_err(|e| anyhow!("parsing latency-sample: {}", e))?
+        .or_else(|| {
+            (args.contains_id("latency") || latency_file.is_some()).then_some(1)
+        });
+    let options = TouchOptions {
+        rate,
+        nthreads,
+        latency_sample,
+        latency_file,
+        percent: None,
+    };
+
+    if args.contains_id("background") {
+        let id = swappy.swap_touch_background(addr_usize, &options)?;
+        return background_output(swappy, id);
+    }
+
+    let delta_only = args.contains_id("delta-only");
+    let before = AccountingSnapshot::fetch()?;
     let mut s = String::new();
-    if !swappy.swap_touch(addr_usize)? {
+    let report = swappy.swap_touch(addr_usize, &options)?;
+    // Report a failure to save the samples after the report itself.
+    let save_error = options
+        .latency_file
+        .as_ref()
+        .and_then(|path| report.save_latency_samples(path).err());
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!({
+            "addr": format!("0x{:x}", addr_usize),
+            "accounting": accounting_json(&before)?,
+            "report": report.summary(),
+            "latency_file_error": save_error.map(|e| format!("{:#}", e)),
+        }));
+    }
+    if report.already_touched {
         s.push_str("warning: pages were already touched\n");
     }
+    if report.outcome == JobOutcome::Cancelled {
+        writeln!(s, "interrupted after touching {} pages", report.npages())
+            .unwrap();
+    }
 
-    let swapinfo = Swappy::swap_info()?;
-    write!(s, "{}", swapinfo.display()).unwrap();
+    s.push_str(&do_print_accounting_delta(&before, delta_only)?);
+    if !delta_only {
+        s.push('\n');
+        write!(s, "{}", report).unwrap();
+    }
+    if let Some(error) = save_error {
+        if !s.ends_with('\n') {
+            s.push('\n');
+        }
+        write!(s, "warning: {:#}", error).unwrap();
+    }
+    Ok(Some(s))
+}
+
+fn cmd_swap_residency(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let addr = args
+        .get_one::<String>("addr")
+        .map(|addr_str| parse_int::parse::<usize>(addr_str))
+        .transpose()
+        .map_err(|e| anyhow!("parsing addr: {}", e))?;
+    let residencies = swappy.swap_residency(addr)?;
+    if swappy.output_format() == OutputFormat::Json {
+        let mappings: Vec<_> = residencies
+            .iter()
+            .map(|(m, residency)| {
+                serde_json::json!({
+                    "addr": format!("{:p}", m.addr),
+                    "size": m.size(),
+                    "busy": m.busy(),
+                    "resident": residency.as_ref().map(|r| r.resident()),
+                    "nonresident": residency.as_ref().map(|r| r.nonresident()),
+                })
+            })
+            .collect();
+        return json_output(serde_json::json!({ "mappings": mappings }));
+    }
+
+    let mut s = String::new();
+    writeln!(s, "MAPPING RESIDENCY").unwrap();
+    writeln!(
+        s,
+        "{:18}  {:10}  {:14}  {:17}  {:12}",
+        "ADDR",
+        "SIZE (GiB)",
+        "RESIDENT (GiB)",
+        "NONRESIDENT (GiB)",
+        "RESIDENT (%)"
+    )
+    .unwrap();
+    for (m, residency) in residencies {
+        let residency = match residency {
+            Some(residency) => residency,
+            None => {
+                writeln!(
+                    s,
+                    "{:18p}  {:10}  BUSY",
+                    m.addr,
+                    ByteSizeDisplayGiB(m.size()),
+                )
+                .unwrap();
+                continue;
+            }
+        };
+        writeln!(
+            s,
+            "{:18p}  {:10}  {:14}  {:17}  {:12.1}",
+            m.addr,
+            ByteSizeDisplayGiB(m.size()),
+            ByteSizeDisplayGiB(residency.resident()),
+            ByteSizeDisplayGiB(residency.nonresident()),
+            residency.resident_pct(),
+        )
+        .unwrap();
+    }
+    Ok(Some(s))
+}
+
+fn cmd_monitor_output(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    if let Some(output_strs) = args.get_many::<String>("output") {
+        let output_strs: Vec<&String> = output_strs.collect();
+        let outputs = if output_strs.len() == 1 && output_strs[0] == "none" {
+            Vec::new()
+        } else {
+            output_strs
+                .into_iter()
+                .map(|o| MonitorOutput::from_str(o))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        swappy.monitor_set_outputs(outputs)?;
+    }
+
+    let outputs = swappy.monitor_outputs();
+    if swappy.output_format() == OutputFormat::Json {
+        let outputs: Vec<String> =
+            outputs.iter().map(|o| o.to_string()).collect();
+        return json_output(serde_json::json!({ "outputs": outputs }));
+    }
+
+    let mut s = String::new();
+    writeln!(s, "MONITOR OUTPUTS").unwrap();
+    if outputs.is_empty() {
+        writeln!(s, "none").unwrap();
+    }
+    for output in outputs {
+        writeln!(s, "{}", output).unwrap();
+    }
+    Ok(Some(s))
+}
+
+fn cmd_monitor_config(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let mut config = swappy.monitor_config();
+    if let Some(interval_str) = args.get_one::<String>("interval") {
+        config.interval =
+            parse_duration(interval_str).context("parsing interval")?;
+    }
+    if let Some(columns_str) = args.get_one::<String>("columns") {
+        config.columns = columns_str
+            .split(',')
+            .map(Stat::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    if let Some(units_str) = args.get_one::<String>("units") {
+        config.units = Units::from_str(units_str)?;
+    }
+    if let Some(header_str) = args.get_one::<String>("header-every") {
+        let header_every: usize = parse_int::parse(header_str)
+            .map_err(|e| anyhow!("parsing header-every: {}", e))?;
+        config.header_every = (header_every != 0).then_some(header_every);
+    }
+    if let Some(rates_str) = args.get_one::<String>("rates") {
+        config.rates = if rates_str == "none" {
+            Vec::new()
+        } else {
+            rates_str
+                .split(',')
+                .map(Stat::from_str)
+                .collect::<Result<Vec<_>, _>>()?
+        };
+    }
+    if let Some(units_str) = args.get_one::<String>("rate-units") {
+        config.rate_units = Units::from_str(units_str)?;
+    }
+    if let Some(timestamps_str) = args.get_one::<String>("timestamps") {
+        config.timestamps = timestamps_str == "on";
+    }
+    swappy.monitor_set_config(config)?;
+
+    let config = swappy.monitor_config();
+    let columns: Vec<&str> = config.columns.iter().map(|c| c.name()).collect();
+    let rates: Vec<&str> = config.rates.iter().map(|c| c.name()).collect();
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!({
+            "interval_secs": config.interval.as_secs_f64(),
+            "columns": columns,
+            "units": config.units.to_string(),
+            "header_every": config.header_every,
+            "rates": rates,
+            "rate_units": config.rate_units.to_string(),
+            "timestamps": config.timestamps,
+        }));
+    }
+
+    let mut s = String::new();
+    writeln!(s, "MONITOR CONFIGURATION").unwrap();
+    writeln!(s, "interval:          {:?}", config.interval).unwrap();
+    writeln!(s, "columns:           {}", columns.join(",")).unwrap();
+    writeln!(s, "units:             {}", config.units).unwrap();
+    match config.header_every {
+        Some(n) => writeln!(s, "header every:      {} rows", n).unwrap(),
+        None => writeln!(s, "header every:      never").unwrap(),
+    }
+    if rates.is_empty() {
+        writeln!(s, "rates:             none").unwrap();
+    } else {
+        writeln!(s, "rates:             {}", rates.join(",")).unwrap();
+    }
+    writeln!(s, "rate units:        {}/s", config.rate_units).unwrap();
+    writeln!(
+        s,
+        "timestamps:        {}",
+        if config.timestamps { "on" } else { "off" }
+    )
+    .unwrap();
+    writeln!(s, "available columns: {}", stat_names()).unwrap();
+    Ok(Some(s))
+}
+
+fn cmd_monitor(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let count = args
+        .get_one::<String>("count")
+        .map(|n| parse_int::parse::<u64>(n))
+        .transpose()
+        .map_err(|e| anyhow!("parsing count: {}", e))?;
+    let interval = args
+        .get_one::<String>("interval")
+        .map(|i| parse_duration(i).context("parsing interval"))
+        .transpose()?;
+
+    if swappy.monitor_watch(count, interval)? {
+        Ok(Some(String::from("interrupted")))
+    } else {
+        Ok(None)
+    }
+}
+
+fn cmd_history(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let since = args
+        .get_one::<String>("since")
+        .map(|since_str| parse_duration(since_str).context("parsing since"))
+        .transpose()?;
+    let config = swappy.monitor_config();
+    let columns = match args.get_many::<String>("column") {
+        Some(names) => {
+            names.map(|n| Stat::from_str(n)).collect::<Result<Vec<_>, _>>()?
+        }
+        None => config.columns.clone(),
+    };
+
+    let samples = swappy.history(since);
+    if swappy.output_format() == OutputFormat::Json {
+        let stats: Vec<_> = columns
+            .iter()
+            .filter_map(|stat| StatSummary::new(*stat, &samples))
+            .map(|summary| {
+                serde_json::json!({
+                    "name": summary.stat.name(),
+                    "min": summary.min,
+                    "max": summary.max,
+                    "avg": summary.avg,
+                })
+            })
+            .collect();
+        return json_output(serde_json::json!({
+            "samples": samples.len(),
+            "from": samples.first().map(|s| s.unix_time()),
+            "to": samples.last().map(|s| s.unix_time()),
+            "stats": stats,
+        }));
+    }
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(Some(String::from("no samples collected"))),
+    };
+
+    let mut s = String::new();
+    writeln!(
+        s,
+        "{} samples from {} to {}",
+        samples.len(),
+        format_time_of_day(first.time),
+        format_time_of_day(last.time)
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "{:24}  {:>10}  {:>10}  {:>10}  {:5}  CHART",
+        "STAT", "MIN", "MAX", "AVG", "UNITS"
+    )
+    .unwrap();
+    for stat in columns {
+        let summary = match StatSummary::new(stat, &samples) {
+            Some(summary) => summary,
+            None => continue,
+        };
+        let (min, max, avg, units) = match stat.kind() {
+            StatKind::Bytes => (
+                ByteSizeDisplayIn(ByteSize::b(summary.min), config.units)
+                    .to_string(),
+                ByteSizeDisplayIn(ByteSize::b(summary.max), config.units)
+                    .to_string(),
+                ByteSizeDisplayIn(
+                    ByteSize::b(summary.avg.round() as u64),
+                    config.units,
+                )
+                .to_string(),
+                config.units.to_string(),
+            ),
+            // Counters are summarized as the change per sample.
+            StatKind::Counter => (
+                summary.min.to_string(),
+                summary.max.to_string(),
+                format!("{:.1}", summary.avg),
+                String::from("delta"),
+            ),
+            StatKind::Pressure => (
+                level_name(summary.min),
+                level_name(summary.max),
+                format!("{:.1}", summary.avg),
+                String::from("level"),
+            ),
+        };
+        writeln!(
+            s,
+            "{:24}  {:>10}  {:>10}  {:>10}  {:5}  {}",
+            stat.name(),
+            min,
+            max,
+            avg,
+            units,
+            summary.sparkline(40)
+        )
+        .unwrap();
+    }
+    Ok(Some(s))
+}
+
+/// Returns the name of the pressure level with number `value`
+fn level_name(value: u64) -> String {
+    PressureLevel::from_u64(value)
+        .map(|l| l.to_string())
+        .unwrap_or_else(|| value.to_string())
+}
+
+fn cmd_at(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    do_schedule(args, swappy, false)
+}
+
+fn cmd_every(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    do_schedule(args, swappy, true)
+}
+
+/// Commands that "at" and "every" can run
+///
+/// Scheduled commands run on the scheduler thread while the REPL is reading
+/// input, so Ctrl-C can't interrupt them.  This leaves out commands that run
+/// until Ctrl-C or until a condition holds, that run other commands, and that
+/// change the REPL's settings (which a scheduled command can't see anyway).
+const SCHEDULABLE_COMMANDS: &[&str] = &[
+    "memstat",
+    "swap-info",
+    "pressure",
+    "swap-mappings",
+    "swap-reserve",
+    "swap-noreserve",
+    "swap-rm",
+    "swap-touch",
+    "swap-residency",
+    "monitor",
+    "monitor-output",
+    "monitor-config",
+    "history",
+    "alert",
+    "alert-rm",
+    "snapshot",
+    "schedule",
+    "jobs",
+    "job-cancel",
+    "kstat-dump",
+];
+
+fn do_schedule(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+    repeat: bool,
+) -> Result<Option<String>, SwappyError> {
+    // clap has already checked that the arguments were provided.
+    let when_str = args.get_one::<String>("when").unwrap();
+    let when = parse_duration(when_str).context("parsing time")?;
+    if repeat && when.is_zero() {
+        return Err(anyhow!("interval must be non-zero").into());
+    }
+    let argv: Vec<String> =
+        args.get_many::<String>("command").unwrap().cloned().collect();
+
+    // Check the command now so that mistakes are reported right away rather
+    // than when it runs.
+    let (_, command_args) = parse_command(&argv)?;
+    let name = argv[0].as_str();
+    if !SCHEDULABLE_COMMANDS.contains(&name) {
+        return Err(anyhow!(
+            "{:?} cannot be scheduled (scheduled commands must be one of: {})",
+            name,
+            SCHEDULABLE_COMMANDS.join(", ")
+        )
+        .into());
+    }
+    if name == "monitor" && !command_args.contains_id("count") {
+        return Err(anyhow!("\"monitor\" needs --count to be scheduled").into());
+    }
+
+    let description = argv.join(" ");
+    let label = description.clone();
+    let mut handle = swappy.clone();
+    let action = move || {
+        let now = format_time_of_day(std::time::SystemTime::now());
+        match run_command(&mut handle, &argv) {
+            Ok(Some(output)) => println!("[{}] {}\n{}", now, label, output),
+            Ok(None) => println!("[{}] {}", now, label),
+            Err(error) => println!("[{}] {}: failed: {}", now, label, error),
+        }
+    };
+    let every = repeat.then_some(when);
+    let id = swappy.schedule_add(description, when, every, action);
+    match swappy.output_format() {
+        OutputFormat::Text => Ok(Some(format!("scheduled command {}", id))),
+        OutputFormat::Json => {
+            json_output(serde_json::json!({ "scheduled": id }))
+        }
+    }
+}
+
+fn cmd_watch(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let interval = args
+        .get_one::<String>("interval")
+        .map(|i| parse_duration(i).context("parsing interval"))
+        .transpose()?
+        .unwrap_or(std::time::Duration::from_secs(2));
+    // clap has already checked that the argument was provided.
+    let argv: Vec<String> =
+        args.get_many::<String>("command").unwrap().cloned().collect();
+    parse_command(&argv)?;
+
+    let interrupt = interrupt::interruptible();
+    let header = format!(
+        "every {:.1}s: {} (Ctrl-C to stop)",
+        interval.as_secs_f64(),
+        argv.join(" ")
+    );
+    let mut previous: Option<String> = None;
+    loop {
+        let output = match run_command(swappy, &argv) {
+            Ok(output) => output.unwrap_or_default(),
+            Err(error) => format!("error: {}", error),
+        };
+        if interrupt.interrupted() {
+            break;
+        }
+
+        // Clear the screen and move the cursor to the top left.
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{}  {}\n",
+            header,
+            format_time_of_day(std::time::SystemTime::now())
+        );
+        println!("{}", highlight_changes(previous.as_deref(), &output));
+        previous = Some(output);
+
+        if interrupt::sleep(interval) {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Returns `current` with the words that differ from the same word (by
+/// position) in `previous` highlighted
+fn highlight_changes(previous: Option<&str>, current: &str) -> String {
+    let previous_lines: Vec<&str> =
+        previous.map(|p| p.lines().collect()).unwrap_or_default();
+    let mut rv = String::new();
+    for (i, line) in current.lines().enumerate() {
+        let mut previous_words =
+            previous_lines.get(i).map(|l| l.split_whitespace());
+        // Walk alternating runs of whitespace and non-whitespace so that the
+        // line's spacing (and therefore any table alignment) is preserved.
+        let mut rest = line;
+        while !rest.is_empty() {
+            let space_len = rest.len() - rest.trim_start().len();
+            rv.push_str(&rest[..space_len]);
+            rest = &rest[space_len..];
+            let word_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if word_len == 0 {
+                break;
+            }
+            let word = &rest[..word_len];
+            rest = &rest[word_len..];
+            let previous_word =
+                previous_words.as_mut().and_then(|words| words.next());
+            match previous_word {
+                Some(previous_word) if previous_word == word => {
+                    rv.push_str(word)
+                }
+                // Don't highlight everything on the first refresh.
+                _ if previous.is_none() => rv.push_str(word),
+                _ => rv.push_str(&paint_yellow_bold(word)),
+            }
+        }
+        rv.push('\n');
+    }
+    rv
+}
+
+fn cmd_schedule(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    match args.subcommand() {
+        Some(("list", _)) if swappy.output_format() == OutputFormat::Json => {
+            let scheduled: Vec<_> = swappy
+                .schedule_list()
+                .iter()
+                .map(|scheduled| {
+                    serde_json::json!({
+                        "id": scheduled.id,
+                        "command": scheduled.description,
+                        "next_in_secs": scheduled.next_in.as_secs_f64(),
+                        "every_secs": scheduled.every.map(|e| e.as_secs_f64()),
+                        "runs": scheduled.nruns,
+                    })
+                })
+                .collect();
+            json_output(serde_json::json!({ "scheduled": scheduled }))
+        }
+        Some(("list", _)) => {
+            let mut s = String::new();
+            writeln!(
+                s,
+                "{:4}  {:>9}  {:>9}  {:>5}  COMMAND",
+                "ID", "NEXT IN", "EVERY", "RUNS"
+            )
+            .unwrap();
+            for scheduled in swappy.schedule_list() {
+                writeln!(
+                    s,
+                    "{:4}  {:>8.1}s  {:>9}  {:>5}  {}",
+                    scheduled.id,
+                    scheduled.next_in.as_secs_f64(),
+                    scheduled
+                        .every
+                        .map(|e| format!("{:.1}s", e.as_secs_f64()))
+                        .unwrap_or_else(|| String::from("-")),
+                    scheduled.nruns,
+                    scheduled.description,
+                )
+                .unwrap();
+            }
+            Ok(Some(s))
+        }
+        Some(("cancel", args)) => {
+            let id_str: &String =
+                args.get_one("id").context("\"id\" argument")?;
+            let id: usize = parse_int::parse(id_str)
+                .map_err(|e| anyhow!("parsing id: {}", e))?;
+            swappy.schedule_cancel(id)?;
+            Ok(None)
+        }
+        _ => Err(anyhow!("expected a schedule subcommand").into()),
+    }
+}
+
+fn cmd_snapshot(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    match args.subcommand() {
+        Some(("save", args)) => {
+            let name: &String =
+                args.get_one("name").context("\"name\" argument")?;
+            let snapshot = swappy.snapshot(name)?;
+            let path = snapshot.save()?;
+            match swappy.output_format() {
+                OutputFormat::Text => {
+                    Ok(Some(format!("saved snapshot {:?} to {:?}", name, path)))
+                }
+                OutputFormat::Json => json_output(
+                    serde_json::json!({ "name": name, "path": path }),
+                ),
+            }
+        }
+        Some(("diff", args)) => {
+            let a: &String = args.get_one("a").context("\"a\" argument")?;
+            let b: &String = args.get_one("b").context("\"b\" argument")?;
+            if swappy.output_format() == OutputFormat::Json {
+                // The snapshots themselves are JSON files.
+                return Err(anyhow!(
+                    "\"snapshot diff\" does not support JSON output (the \
+                    snapshots are saved as JSON in {:?})",
+                    Snapshot::dir()
+                )
+                .into());
+            }
+            let a = Snapshot::load(a)?;
+            let b = Snapshot::load(b)?;
+            Ok(Some(a.diff(&b).to_string()))
+        }
+        Some(("list", _)) if swappy.output_format() == OutputFormat::Json => {
+            let snapshots: Vec<_> = Snapshot::list()?
+                .iter()
+                .map(|snapshot| {
+                    serde_json::json!({
+                        "name": snapshot.name,
+                        "time": snapshot.time,
+                        "mappings": snapshot.mappings.len(),
+                        "memstat": snapshot.memstat.is_some(),
+                    })
+                })
+                .collect();
+            json_output(serde_json::json!({ "snapshots": snapshots }))
+        }
+        Some(("list", _)) => {
+            let mut s = String::new();
+            writeln!(s, "snapshots in {:?}", Snapshot::dir()).unwrap();
+            writeln!(
+                s,
+                "{:24}  {:13}  {:>8}  MEMSTAT",
+                "NAME", "TIME", "MAPPINGS"
+            )
+            .unwrap();
+            for snapshot in Snapshot::list()? {
+                writeln!(
+                    s,
+                    "{:24}  {:13}  {:>8}  {}",
+                    snapshot.name,
+                    format_time_of_day(snapshot.system_time()),
+                    snapshot.mappings.len(),
+                    if snapshot.memstat.is_some() { "yes" } else { "no" },
+                )
+                .unwrap();
+            }
+            Ok(Some(s))
+        }
+        _ => Err(anyhow!("expected a snapshot subcommand").into()),
+    }
+}
+
+fn cmd_alert(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    if let Some(words) = args.get_many::<String>("condition") {
+        let condition_str =
+            words.map(|w| w.as_str()).collect::<Vec<_>>().join(" ");
+        let condition = Condition::from_str(&condition_str)?;
+        let action = if args.contains_id("stop-touches") {
+            AlertAction::StopTouches
+        } else {
+            AlertAction::Print
+        };
+        let id = swappy.alert_add(condition, action)?;
+        return match swappy.output_format() {
+            OutputFormat::Text => Ok(Some(format!("alert {} added", id))),
+            OutputFormat::Json => {
+                json_output(serde_json::json!({ "alert": id }))
+            }
+        };
+    }
+
+    if swappy.output_format() == OutputFormat::Json {
+        let alerts: Vec<_> = swappy
+            .alerts()
+            .iter()
+            .map(|alert| {
+                serde_json::json!({
+                    "id": alert.id,
+                    "action": alert.action.to_string(),
+                    "condition": alert.condition.to_string(),
+                })
+            })
+            .collect();
+        return json_output(serde_json::json!({ "alerts": alerts }));
+    }
+
+    let mut s = String::new();
+    writeln!(s, "{:4}  {:12}  CONDITION", "ID", "ACTION").unwrap();
+    for alert in swappy.alerts() {
+        writeln!(s, "{:4}  {:12}  {}", alert.id, alert.action, alert.condition)
+            .unwrap();
+    }
+    Ok(Some(s))
+}
+
+fn cmd_wait_until(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    // clap has already checked that the argument was provided.
+    let condition_str = args
+        .get_many::<String>("condition")
+        .unwrap()
+        .map(|w| w.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let condition = Condition::from_str(&condition_str)?;
+    let timeout = args
+        .get_one::<String>("timeout")
+        .map(|t| parse_duration(t).context("parsing timeout"))
+        .transpose()?;
+
+    let start = std::time::Instant::now();
+    match swappy.wait_until(&condition, timeout)? {
+        Some(_) if swappy.output_format() == OutputFormat::Json => {
+            json_output(serde_json::json!({
+                "condition": condition.to_string(),
+                "elapsed_secs": start.elapsed().as_secs_f64(),
+            }))
+        }
+        Some(sample) => Ok(Some(format!(
+            "{} after {:.1}s ({})",
+            condition,
+            start.elapsed().as_secs_f64(),
+            condition.describe_values(&sample)
+        ))),
+        None => Err(SwappyError(anyhow!("interrupted"))),
+    }
+}
+
+fn cmd_alert_rm(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let id_str: &String = args.get_one("id").context("\"id\" argument")?;
+    let id: usize =
+        parse_int::parse(id_str).map_err(|e| anyhow!("parsing id: {}", e))?;
+    swappy.alert_rm(id)?;
+    Ok(None)
+}
+
+fn cmd_jobs(
+    _args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let jobs = swappy.jobs();
+    if swappy.output_format() == OutputFormat::Json {
+        let jobs: Vec<_> = jobs
+            .iter()
+            .map(|job| {
+                let error = match &job.state {
+                    JobState::Failed(message) => Some(message),
+                    _ => None,
+                };
+                serde_json::json!({
+                    "id": job.id,
+                    "description": job.description,
+                    "state": job.state.to_string(),
+                    "done": job.done,
+                    "total": job.total,
+                    "error": error,
+                })
+            })
+            .collect();
+        return json_output(serde_json::json!({ "jobs": jobs }));
+    }
+
+    let mut s = String::new();
+    writeln!(
+        s,
+        "{:4}  {:9}  {:>8}  {:>11}  JOB",
+        "ID", "STATE", "DONE (%)", "TOTAL (GiB)"
+    )
+    .unwrap();
+    for job in jobs {
+        let pct = if job.total.as_u64() == 0 {
+            100.0
+        } else {
+            100.0 * (job.done.as_u64() as f64) / (job.total.as_u64() as f64)
+        };
+        writeln!(
+            s,
+            "{:4}  {:9}  {:8.1}  {:11}  {}",
+            job.id,
+            job.state,
+            pct,
+            ByteSizeDisplayGiB(job.total),
+            job.description,
+        )
+        .unwrap();
+        if let JobState::Failed(message) = &job.state {
+            writeln!(s, "      error: {}", message).unwrap();
+        }
+    }
     Ok(Some(s))
 }
 
+fn cmd_job_cancel(
+    args: ArgMatches,
+    swappy: &mut Swappy,
+) -> Result<Option<String>, SwappyError> {
+    let id_str: &String = args.get_one("id").context("\"id\" argument")?;
+    let id: usize =
+        parse_int::parse(id_str).map_err(|e| anyhow!("parsing id: {}", e))?;
+    swappy.job_cancel(id)?;
+    Ok(None)
+}
+
 fn cmd_kstat_dump(
     _args: ArgMatches,
     swappy: &mut Swappy,
 ) -> Result<Option<String>, SwappyError> {
-    let physmem = swappy.kstat_read()?;
+    let sample = swappy.kstat_read()?;
+    if swappy.output_format() == OutputFormat::Json {
+        return json_output(serde_json::json!({
+            "physmem": sample.physmem,
+            "vm": sample.vm,
+            "kmem_reaps": sample.kmem_reaps,
+            "arc": sample.arc,
+            "zone_swap_failures": sample.zone_swap_failures,
+        }));
+    }
     let mut s = String::new();
-    write!(s, "{:?}", physmem).unwrap();
+    writeln!(s, "{:?}", sample.physmem).unwrap();
+    match &sample.vm {
+        Some(vm) => writeln!(s, "{:?}", vm).unwrap(),
+        None => writeln!(s, "vm activity: not available").unwrap(),
+    }
+    match sample.kmem_reaps {
+        Some(reaps) => writeln!(s, "kmem reaps: {}", reaps).unwrap(),
+        None => writeln!(s, "kmem reaps: not available").unwrap(),
+    }
+    match &sample.arc {
+        Some(arc) => writeln!(s, "{:?}", arc).unwrap(),
+        None => writeln!(s, "ARC: not present").unwrap(),
+    }
+    match &sample.zone_swap_failures {
+        Some(failures) => write!(s, "{:?}", failures).unwrap(),
+        None => write!(s, "zone swap failures: not available").unwrap(),
+    }
     Ok(Some(s))
 }
+
+/// Invoked after each command to call out any swap allocation failures caused
+/// by zone caps that happened while it ran
+fn after_command(swappy: &mut Swappy) {
+    let failures = match swappy.zone_swap_failures_since_last_check() {
+        Ok(failures) => failures,
+        Err(error) => {
+            eprintln!("warning: checking for zone swap failures: {:#}", error);
+            return;
+        }
+    };
+    if !failures.is_zero() {
+        eprintln!(
+            "{}",
+            paint_yellow_bold(&format!(
+                "warning: zone cap swap failures since the last command: \
+                {} anon allocation failures, {} reservation failures",
+                failures.anon_alloc_failures, failures.swap_reserve_failures
+            ))
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::highlight_changes;
+    use super::parse_duration;
+    use reedline_repl_rs::paint_yellow_bold;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            parse_duration("500ms").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("0s").unwrap(), Duration::ZERO);
+
+        assert_eq!(
+            parse_duration("5x").unwrap_err().to_string(),
+            "\"5x\": invalid float literal"
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("1 s").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn test_highlight_changes() {
+        let current = "SIZE  USED\n  10     3\n";
+        // Nothing is highlighted the first time.
+        assert_eq!(highlight_changes(None, current), current);
+        assert_eq!(highlight_changes(Some(current), current), current);
+
+        // Changed words are highlighted, keeping the spacing.
+        assert_eq!(
+            highlight_changes(Some("SIZE  USED\n  10     2"), current),
+            format!("SIZE  USED\n  10     {}\n", paint_yellow_bold("3"))
+        );
+        // Words are compared by position, not by column.
+        assert_eq!(
+            highlight_changes(Some("SIZE USED\n10 3"), current),
+            current
+        );
+        // New words and lines are highlighted, too.
+        assert_eq!(
+            highlight_changes(Some("SIZE"), current),
+            format!(
+                "SIZE  {}\n  {}     {}\n",
+                paint_yellow_bold("USED"),
+                paint_yellow_bold("10"),
+                paint_yellow_bold("3")
+            )
+        );
+        assert_eq!(highlight_changes(Some("a\nb"), ""), "");
+    }
+}
@@ -0,0 +1,58 @@
+//! Parsing the output of mdb's `::memstat`
+
+use crate::PAGE_SIZE;
+use bytesize::ByteSize;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// One row of `::memstat` output: how much memory a kernel consumer is using
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemstatRow {
+    /// what's using the memory (e.g., "Kernel", "Anon", "Free (freelist)")
+    pub category: String,
+    /// number of pages used
+    pub pages: u64,
+}
+
+impl MemstatRow {
+    /// Returns the amount of memory used
+    pub fn bytes(&self) -> ByteSize {
+        ByteSize::b(self.pages * (PAGE_SIZE as u64))
+    }
+}
+
+/// Parses the table printed by `::memstat`
+///
+/// The output looks like this:
+///
+/// ```text
+/// Page Summary                 Pages             Bytes  %Tot
+/// ---------------------  ----------------  ----------------  ----
+/// Kernel                         1234567              4.7G   12%
+/// Anon                            234567            916.2M    2%
+/// ...
+/// Total                         10485760               40G
+/// ```
+///
+/// Only the category and page count are kept; the other columns are derived
+/// from those.  Lines that don't look like rows of the table are skipped.
+pub fn parse_memstat(output: &str) -> Vec<MemstatRow> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            // Find the page count: the first numeric word after the category
+            // (which may itself contain spaces, e.g., "Free (freelist)").
+            let which = words.iter().position(|w| w.parse::<u64>().is_ok())?;
+            if which == 0 {
+                return None;
+            }
+            Some(MemstatRow {
+                category: words[..which].join(" "),
+                pages: words[which].parse().ok()?,
+            })
+        })
+        .collect()
+}
@@ -1,41 +1,320 @@
 //! Monitor subsystem
 //!
 //! Functions that expect to take a while and cause interesting effects on the
-//! system can enable the monitor to print stats once / second and disable the
-//! monitor to stop printing stats.
+//! system can enable the monitor to print stats at the configured interval
+//! and disable the monitor to stop printing stats.  Where the stats go is configured with
+//! [`Monitor::set_outputs()`].  What's collected and how often is configured
+//! with [`Monitor::set_config()`].
 
-use crate::bytesize_display::ByteSizeDisplayGiB;
+use crate::alert::Alert;
+use crate::alert::AlertAction;
+use crate::alert::Condition;
+use crate::bytesize_display::Units;
+use crate::history::History;
+use crate::interrupt::InterruptGuard;
+use crate::kstat::kstat_read_arc;
+use crate::kstat::kstat_read_kmem_reaps;
 use crate::kstat::kstat_read_physmem;
+use crate::kstat::kstat_read_vm_activity;
+use crate::kstat::kstat_read_zone_swap_failures;
+use crate::kstat::ArcStats;
+use crate::kstat::PhysicalMemoryStats;
+use crate::kstat::VmActivityStats;
+use crate::kstat::ZoneSwapFailureStats;
+use crate::monitor_output::MonitorOutput;
+use crate::monitor_output::MonitorSink;
+use crate::output::format_time_of_day;
+use crate::stats::Stat;
 use crate::swap::AnonInfo;
+use crate::touch::ActiveTouches;
+use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
+use reedline_repl_rs::paint_yellow_bold;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+/// Smallest supported interval between samples
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Describes what the monitor collects and how it's printed
+#[derive(Clone, Debug)]
+pub struct MonitorConfig {
+    /// how often to collect a sample
+    pub interval: Duration,
+    /// which stats to print in the terminal table
+    pub columns: Vec<Stat>,
+    /// units for values printed in the terminal table
+    pub units: Units,
+    /// if specified, reprint the terminal table header after this many rows
+    pub header_every: Option<usize>,
+    /// stats whose per-second rate of change to print in the terminal table
+    pub rates: Vec<Stat>,
+    /// units (per second) for rates printed in the terminal table
+    pub rate_units: Units,
+    /// whether to print the time of each sample in the terminal table
+    pub timestamps: bool,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        MonitorConfig {
+            interval: Duration::from_secs(1),
+            columns: vec![
+                Stat::Freemem,
+                Stat::SwapAllocated,
+                Stat::SwapReserved,
+                Stat::SwapTotal,
+            ],
+            units: Units::GiB,
+            header_every: None,
+            rates: Vec::new(),
+            rate_units: Units::MiB,
+            timestamps: false,
+        }
+    }
+}
+
+impl MonitorConfig {
+    /// Returns an error if this configuration doesn't make sense
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.interval < MIN_INTERVAL {
+            bail!("monitor interval must be at least {:?}", MIN_INTERVAL);
+        }
+        if self.columns.is_empty() && self.rates.is_empty() {
+            bail!("monitor must have at least one column");
+        }
+        if self.header_every == Some(0) {
+            bail!("header interval must be non-zero");
+        }
+        Ok(())
+    }
+}
 
 /// Handle for the monitor
 // This is essentially a client that sends messages over a channel to the
 // monitor thread and in some cases receives responses back.
-pub struct Monitor {
+pub(crate) struct Monitor {
     #[allow(dead_code)]
     monitor_thread: std::thread::JoinHandle<Result<(), anyhow::Error>>,
     monitor_tx: std::sync::mpsc::SyncSender<MonitorMessage>,
+    outputs: Mutex<Vec<MonitorOutput>>,
+    config: Mutex<MonitorConfig>,
+    alerts: Mutex<Vec<Alert>>,
+    next_alert_id: AtomicUsize,
+    nsamples: Arc<SampleCounter>,
+    history: Arc<Mutex<History>>,
+}
+
+/// Counts samples taken by the monitor thread so that callers can wait for
+/// them (see [`Monitor::wait_samples()`])
+#[derive(Default)]
+struct SampleCounter {
+    count: Mutex<u64>,
+    cv: Condvar,
+}
+
+impl SampleCounter {
+    fn increment(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.cv.notify_all();
+    }
 }
 
 impl Monitor {
     /// Starts a background thread for monitoring and returns a [`Monitor`]
     /// handle that can be used to turn monitoring on or off
-    pub fn new() -> Monitor {
+    ///
+    /// `touches` is used to stop touches in progress when an alert with
+    /// [`AlertAction::StopTouches`] fires.
+    pub fn new(touches: Arc<ActiveTouches>) -> Monitor {
         let (monitor_tx, monitor_rx) = std::sync::mpsc::sync_channel(4);
+        let nsamples = Arc::new(SampleCounter::default());
+        let thread_nsamples = Arc::clone(&nsamples);
+        let history = Arc::new(Mutex::new(History::new()));
+        let thread_history = Arc::clone(&history);
         Monitor {
             monitor_thread: std::thread::spawn(move || {
-                monitor_thread(monitor_rx)
+                monitor_thread(
+                    monitor_rx,
+                    &thread_nsamples,
+                    &thread_history,
+                    &touches,
+                )
             }),
             monitor_tx,
+            outputs: Mutex::new(vec![MonitorOutput::Terminal]),
+            config: Mutex::new(MonitorConfig::default()),
+            alerts: Mutex::new(Vec::new()),
+            next_alert_id: AtomicUsize::new(1),
+            nsamples,
+            history,
+        }
+    }
+
+    /// Waits until the monitor thread has taken `count` more samples (or
+    /// forever, if `count` is `None`), returning early if interrupted or if
+    /// `deadline` passes
+    ///
+    /// The monitor must be enabled while this is called.  Returns `true` if
+    /// interrupted.
+    pub fn wait_samples(
+        &self,
+        count: Option<u64>,
+        deadline: Option<Instant>,
+        interrupt: &InterruptGuard,
+    ) -> bool {
+        let mut nsamples = self.nsamples.count.lock().unwrap();
+        let target = count.map(|c| *nsamples + c);
+        loop {
+            if interrupt.interrupted() {
+                return true;
+            }
+            if let Some(target) = target {
+                if *nsamples >= target {
+                    return false;
+                }
+            }
+
+            // Wake up periodically to check for an interrupt.
+            let mut timeout = Duration::from_millis(100);
+            if let Some(deadline) = deadline {
+                let remaining =
+                    deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return false;
+                }
+                timeout = timeout.min(remaining);
+            }
+            nsamples =
+                self.nsamples.cv.wait_timeout(nsamples, timeout).unwrap().0;
+        }
+    }
+
+    /// Returns the samples collected within the last `since` (or all retained
+    /// samples, if `since` is `None`), oldest first
+    pub fn history(&self, since: Option<Duration>) -> Vec<Arc<MonitorSample>> {
+        self.history.lock().unwrap().samples(since)
+    }
+
+    /// Returns the monitor's current configuration
+    pub fn config(&self) -> MonitorConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    /// Replace the monitor's configuration
+    ///
+    /// This takes effect starting with the next sample.
+    pub fn set_config(
+        &self,
+        config: MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        config.validate()?;
+
+        // As in `set_outputs()`, hold the lock while we wait for the monitor
+        // thread.
+        let mut current_config = self.config.lock().unwrap();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.monitor_tx
+            .send(MonitorMessage::SetConfig(config.clone(), tx))
+            // This is likely that the other thread panicked.
+            .map_err(|_| anyhow!("failed to send config to monitor thread"))?;
+        rx.recv().context("waiting for monitor thread")?;
+        *current_config = config;
+        Ok(())
+    }
+
+    /// Returns the alerts that the monitor checks on each sample
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().clone()
+    }
+
+    /// Add an alert to be checked on each sample, returning its id
+    pub fn add_alert(
+        &self,
+        condition: Condition,
+        action: AlertAction,
+    ) -> Result<usize, anyhow::Error> {
+        let mut alerts = self.alerts.lock().unwrap();
+        let id = self.next_alert_id.fetch_add(1, Ordering::SeqCst);
+        let mut new_alerts = alerts.clone();
+        new_alerts.push(Alert { id, condition, action });
+        self.send_alerts(new_alerts.clone())?;
+        *alerts = new_alerts;
+        Ok(id)
+    }
+
+    /// Remove the alert with id `id`
+    pub fn remove_alert(&self, id: usize) -> Result<(), anyhow::Error> {
+        let mut alerts = self.alerts.lock().unwrap();
+        if !alerts.iter().any(|a| a.id == id) {
+            bail!("no alert with id {}", id);
         }
+        let new_alerts: Vec<Alert> =
+            alerts.iter().filter(|a| a.id != id).cloned().collect();
+        self.send_alerts(new_alerts.clone())?;
+        *alerts = new_alerts;
+        Ok(())
+    }
+
+    /// Sends a new list of alerts to the monitor thread and waits for it to
+    /// take effect
+    ///
+    /// As in `set_outputs()`, the caller should hold the `alerts` lock.
+    fn send_alerts(&self, alerts: Vec<Alert>) -> Result<(), anyhow::Error> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.monitor_tx
+            .send(MonitorMessage::SetAlerts(alerts, tx))
+            // This is likely that the other thread panicked.
+            .map_err(|_| anyhow!("failed to send alerts to monitor thread"))?;
+        rx.recv().context("waiting for monitor thread")?;
+        Ok(())
+    }
+
+    /// Returns where the monitor is currently sending stats
+    pub fn outputs(&self) -> Vec<MonitorOutput> {
+        self.outputs.lock().unwrap().clone()
+    }
+
+    /// Configure where the monitor sends stats, replacing the current
+    /// configuration
+    ///
+    /// Files for file-based outputs are created immediately.  If any of them
+    /// can't be created, the configuration is left unchanged.
+    pub fn set_outputs(
+        &self,
+        outputs: Vec<MonitorOutput>,
+    ) -> Result<(), anyhow::Error> {
+        let sinks = outputs
+            .iter()
+            .map(|o| o.open().with_context(|| format!("monitor output {}", o)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Hold the lock while we wait for the monitor thread so that concurrent
+        // callers can't leave `outputs` out of sync with what the thread has.
+        let mut current_outputs = self.outputs.lock().unwrap();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.monitor_tx
+            .send(MonitorMessage::SetSinks(sinks, tx))
+            // This is likely that the other thread panicked.
+            .map_err(|_| anyhow!("failed to send sinks to monitor thread"))?;
+        rx.recv().context("waiting for monitor thread")?;
+        *current_outputs = outputs;
+        Ok(())
     }
 
     /// Enable monitoring
     ///
     /// This causes the background thread to start collecting and printing stats
-    /// once per second.
+    /// at the interval configured with [`Monitor::set_config()`].  Each call
+    /// must be paired with a call to [`Monitor::disable()`].
     pub fn enable(&self) {
         if let Err(error) = self.monitor_tx.send(MonitorMessage::StartStats) {
             // This is likely that the other thread panicked.
@@ -45,8 +324,9 @@ impl Monitor {
 
     /// Disable monitoring
     ///
-    /// This causes the background thread to stop collecting and printing stats.
-    /// When this function returns, no more stats will be printed.
+    /// This causes the background thread to stop collecting and printing stats
+    /// (unless some other caller still has it enabled).  When this function
+    /// returns, no more stats will be printed on behalf of this caller.
     pub fn disable(&self) {
         // Create a channel (functioning as a oneshot) for the monitor thread to
         // let us know when it's done.  We'll wait for the response.  If we
@@ -74,69 +354,259 @@ enum MonitorMessage {
 
     /// Stop collecting and printing stats and send an ACK message when done
     StopStats(std::sync::mpsc::SyncSender<()>),
+
+    /// Replace the set of sinks and send an ACK message when done
+    SetSinks(Vec<Box<dyn MonitorSink>>, std::sync::mpsc::SyncSender<()>),
+
+    /// Replace the configuration and send an ACK message when done
+    SetConfig(MonitorConfig, std::sync::mpsc::SyncSender<()>),
+
+    /// Replace the list of alerts and send an ACK message when done
+    SetAlerts(Vec<Alert>, std::sync::mpsc::SyncSender<()>),
 }
 
 /// Background thread that implements the monitor
+///
+/// Since several operations (e.g., background jobs) may want the monitor at the
+/// same time, we keep track of how many have enabled it.  Stats are printed as
+/// long as at least one of them has.
 fn monitor_thread(
     rx: std::sync::mpsc::Receiver<MonitorMessage>,
+    nsamples: &SampleCounter,
+    history: &Mutex<History>,
+    touches: &ActiveTouches,
 ) -> Result<(), anyhow::Error> {
+    let mut nenabled: usize = 0;
+    // alerts, each with whether its condition held for the last sample
+    let mut alerts: Vec<(Alert, bool)> = Vec::new();
+    // most recent sample, used to compute rates of change
+    let mut prev: Option<Arc<MonitorSample>> = None;
+    let mut config = MonitorConfig::default();
+    let mut sinks: Vec<Box<dyn MonitorSink>> =
+        vec![MonitorOutput::Terminal.open()?];
     loop {
-        // Wait indefinitely to be told to start monitoring.
-        match rx.recv().context("waiting for StartStats")? {
-            MonitorMessage::StopStats(_) => panic!("stats already stopped"),
-            MonitorMessage::StartStats => (),
-        }
-
-        // Now we're in monitor mode.  Print a header row.  Then we'll wait
-        // again on the channel until we're told to stop.  The only difference
-        // is that we wait with a timeout.  If we hit the timeout, we fetch and
-        // print stats and then try again.
-
-        println!(
-            "{:5} {:10} {:9} {:10}",
-            "FREE", "SWAP_ALLOC", "SWAP_RESV", "SWAP_TOTAL"
-        );
-
-        loop {
-            match rx.recv_timeout(std::time::Duration::from_secs(1)) {
-                Err(RecvTimeoutError::Timeout) => monitor_print(),
+        // When nobody has enabled the monitor, wait indefinitely to be told to
+        // start.  Otherwise, wait with a timeout.  If we hit the timeout, we
+        // fetch and print stats and then try again.
+        let message = if nenabled == 0 {
+            rx.recv().context("waiting for StartStats")?
+        } else {
+            match rx.recv_timeout(config.interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(sample) =
+                        monitor_sample(&mut sinks, prev.as_deref(), &config)
+                    {
+                        check_alerts(&mut alerts, &sample, touches);
+                        let sample = Arc::new(sample);
+                        history.lock().unwrap().push(Arc::clone(&sample));
+                        prev = Some(sample);
+                    }
+                    nsamples.increment();
+                    continue;
+                }
                 Err(error) => {
                     return Err(error).context("waiting for StopStats")
                 }
-                Ok(MonitorMessage::StartStats) => {
-                    panic!("stats already started")
+                Ok(message) => message,
+            }
+        };
+
+        match message {
+            MonitorMessage::StartStats => {
+                // When we first enter monitor mode, let the sinks know (so
+                // that, e.g., the terminal sink can print a header row).
+                // Rates are computed only between samples taken while the
+                // monitor was continuously enabled.
+                if nenabled == 0 {
+                    prev = None;
+                    sinks_start(&mut sinks, &config);
+                }
+                nenabled += 1;
+            }
+            MonitorMessage::StopStats(tx) => {
+                nenabled =
+                    nenabled.checked_sub(1).expect("stats already stopped");
+                tx.send(()).context("confirming StopStats")?;
+            }
+            MonitorMessage::SetSinks(new_sinks, tx) => {
+                sinks = new_sinks;
+                if nenabled > 0 {
+                    sinks_start(&mut sinks, &config);
                 }
-                Ok(MonitorMessage::StopStats(tx)) => {
-                    tx.send(()).context("confirming StopStats")?;
-                    break;
+                tx.send(()).context("confirming SetSinks")?;
+            }
+            MonitorMessage::SetConfig(new_config, tx) => {
+                config = new_config;
+                if nenabled > 0 {
+                    sinks_start(&mut sinks, &config);
                 }
+                tx.send(()).context("confirming SetConfig")?;
+            }
+            MonitorMessage::SetAlerts(new_alerts, tx) => {
+                // Preserve the state of alerts that we already had so that
+                // they don't fire again.
+                alerts = new_alerts
+                    .into_iter()
+                    .map(|alert| {
+                        let active = alerts
+                            .iter()
+                            .any(|(a, active)| a.id == alert.id && *active);
+                        (alert, active)
+                    })
+                    .collect();
+                tx.send(()).context("confirming SetAlerts")?;
             }
         }
     }
 }
 
-/// Invoked once / second while the monitor is enabled
-fn monitor_print() {
-    if let Err(error) = monitor_print_stats().context("monitor_print()") {
-        eprintln!("warning: {:#}", error);
+/// One set of stats collected by the monitor
+#[derive(Debug)]
+pub struct MonitorSample {
+    /// when the stats were collected
+    pub time: SystemTime,
+    /// when the stats were collected (monotonic, for computing rates)
+    pub instant: Instant,
+    pub physmem: PhysicalMemoryStats,
+    pub swapinfo: AnonInfo,
+    /// pageout activity
+    ///
+    /// This and the other optional sources below are `None` if they couldn't
+    /// be read.  They're not needed for most of what swappy does, so failing
+    /// to read them doesn't fail the whole sample.
+    pub vm: Option<VmActivityStats>,
+    /// kmem reaps since boot (see [`kstat_read_kmem_reaps()`])
+    pub kmem_reaps: Option<u64>,
+    /// ARC size (if ZFS is loaded)
+    pub arc: Option<ArcStats>,
+    /// number of times the ARC target size has dropped since the monitor was
+    /// started
+    ///
+    /// There's no kstat for this.  It's filled in by the monitor from
+    /// consecutive samples (and is always 0 for a freshly-fetched sample).
+    pub arc_shrinks: u64,
+    pub zone_swap_failures: Option<ZoneSwapFailureStats>,
+}
+
+impl MonitorSample {
+    /// Collect the latest stats
+    pub fn fetch() -> Result<MonitorSample, anyhow::Error> {
+        let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
+        let physmem =
+            kstat_read_physmem(&kstat).context("kstat_read_physmem")?;
+        let swapinfo = AnonInfo::fetch()?;
+        let vm = kstat_read_vm_activity(&kstat).ok();
+        let kmem_reaps = kstat_read_kmem_reaps(&kstat).ok();
+        let arc = kstat_read_arc(&kstat).ok().flatten();
+        let zone_swap_failures = kstat_read_zone_swap_failures(&kstat).ok();
+
+        Ok(MonitorSample {
+            time: SystemTime::now(),
+            instant: Instant::now(),
+            physmem,
+            swapinfo,
+            vm,
+            kmem_reaps,
+            arc,
+            arc_shrinks: 0,
+            zone_swap_failures,
+        })
+    }
+
+    /// Returns the time of the sample in seconds since the Unix epoch
+    pub fn unix_time(&self) -> f64 {
+        self.time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0)
     }
 }
 
-/// The meat of `monitor_print()`, which is separated for easier error handling
-fn monitor_print_stats() -> Result<(), anyhow::Error> {
-    let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
-    let physmem = kstat_read_physmem(&kstat).context("kstat_read_physmem")?;
-    let swapinfo = AnonInfo::fetch()?;
+/// Invoked when the monitor starts collecting stats
+fn sinks_start(sinks: &mut [Box<dyn MonitorSink>], config: &MonitorConfig) {
+    for sink in sinks {
+        if let Err(error) = sink.start(config) {
+            eprintln!("warning: monitor: {:#}", error);
+        }
+    }
+}
+
+/// Invoked once per interval while the monitor is enabled
+///
+/// Returns the sample collected, if any.
+fn monitor_sample(
+    sinks: &mut [Box<dyn MonitorSink>],
+    prev: Option<&MonitorSample>,
+    config: &MonitorConfig,
+) -> Option<MonitorSample> {
+    let mut sample = match MonitorSample::fetch() {
+        Ok(sample) => sample,
+        Err(error) => {
+            eprintln!("warning: monitor_sample(): {:#}", error);
+            return None;
+        }
+    };
+
+    if let Some(prev) = prev {
+        let shrunk = match (&prev.arc, &sample.arc) {
+            (Some(prev_arc), Some(arc)) => arc.target < prev_arc.target,
+            _ => false,
+        };
+        sample.arc_shrinks = prev.arc_shrinks + u64::from(shrunk);
+    }
 
-    // TODO add kmem reap, arc reap, pageout activity
+    for sink in sinks {
+        if let Err(error) = sink.sample(&sample, prev, config) {
+            eprintln!("warning: monitor: {:#}", error);
+        }
+    }
 
-    println!(
-        "{:5} {:10} {:9} {:10}",
-        ByteSizeDisplayGiB(physmem.freemem),
-        ByteSizeDisplayGiB(swapinfo.allocated()),
-        ByteSizeDisplayGiB(swapinfo.reserved()),
-        ByteSizeDisplayGiB(swapinfo.total()),
-    );
+    Some(sample)
+}
 
-    Ok(())
+/// Checks each alert against `sample`, printing an event for each one whose
+/// condition has started or stopped holding
+fn check_alerts(
+    alerts: &mut [(Alert, bool)],
+    sample: &MonitorSample,
+    touches: &ActiveTouches,
+) {
+    let when = format_time_of_day(sample.time);
+    for (alert, active) in alerts {
+        let holds = alert.condition.eval(sample);
+        if holds == *active {
+            continue;
+        }
+        *active = holds;
+
+        let values = alert.condition.describe_values(sample);
+        if !holds {
+            println!(
+                "[{}] alert {} cleared: {} ({})",
+                when, alert.id, alert.condition, values
+            );
+            continue;
+        }
+
+        println!(
+            "{}",
+            paint_yellow_bold(&format!(
+                "[{}] alert {} fired: {} ({})",
+                when, alert.id, alert.condition, values
+            ))
+        );
+        match alert.action {
+            AlertAction::Print => (),
+            AlertAction::StopTouches => {
+                let nstopped = touches.cancel_all();
+                println!(
+                    "[{}] alert {}: stopping {} touch{}",
+                    when,
+                    alert.id,
+                    nstopped,
+                    if nstopped == 1 { "" } else { "es" }
+                );
+            }
+        }
+    }
 }
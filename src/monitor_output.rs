@@ -0,0 +1,349 @@
+//! Destinations for stats collected by the monitor
+//!
+//! The monitor thread periodically collects a [`MonitorSample`] and hands it
+//! to each configured sink.  The terminal sink prints the familiar table (with
+//! the columns chosen in the [`MonitorConfig`]).  The CSV and JSON Lines sinks
+//! append one record per sample to a file, always including every [`Stat`] (in
+//! bytes, or as a raw cumulative count for counters), so that the timeline of
+//! an experiment can be analyzed afterwards.
+
+use crate::bytesize_display::ByteSizeDisplayIn;
+use crate::bytesize_display::RateDisplayIn;
+use crate::monitor::MonitorConfig;
+use crate::monitor::MonitorSample;
+use crate::pressure::PressureLevel;
+use crate::stats::Stat;
+use crate::stats::StatKind;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use bytesize::ByteSize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Describes one place where the monitor sends its stats
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MonitorOutput {
+    /// print a table to stdout
+    Terminal,
+    /// write CSV to the given file
+    Csv(PathBuf),
+    /// write JSON Lines (one JSON object per line) to the given file
+    JsonLines(PathBuf),
+}
+
+impl std::str::FromStr for MonitorOutput {
+    type Err = anyhow::Error;
+
+    /// Parses `terminal`, `csv=FILE`, or `jsonl=FILE`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = match s.split_once('=') {
+            Some((kind, path)) => (kind, Some(path)),
+            None => (s, None),
+        };
+        match (kind, path) {
+            ("terminal", None) => Ok(MonitorOutput::Terminal),
+            ("csv", Some(path)) if !path.is_empty() => {
+                Ok(MonitorOutput::Csv(PathBuf::from(path)))
+            }
+            ("jsonl", Some(path)) if !path.is_empty() => {
+                Ok(MonitorOutput::JsonLines(PathBuf::from(path)))
+            }
+            ("csv", _) | ("jsonl", _) => {
+                bail!("monitor output {:?}: expected {}=FILE", s, kind)
+            }
+            _ => Err(anyhow!(
+                "unsupported monitor output {:?} (expected \"terminal\", \
+                \"csv=FILE\", or \"jsonl=FILE\")",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for MonitorOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorOutput::Terminal => f.write_str("terminal"),
+            MonitorOutput::Csv(path) => write!(f, "csv={}", path.display()),
+            MonitorOutput::JsonLines(path) => {
+                write!(f, "jsonl={}", path.display())
+            }
+        }
+    }
+}
+
+impl MonitorOutput {
+    /// Returns a sink that writes to this output
+    ///
+    /// For file-based outputs, this creates (or truncates) the file.
+    pub(crate) fn open(&self) -> Result<Box<dyn MonitorSink>, anyhow::Error> {
+        match self {
+            MonitorOutput::Terminal => Ok(Box::new(TerminalSink { nrows: 0 })),
+            MonitorOutput::Csv(path) => {
+                let mut file = create_file(path)?;
+                let mut header = String::from("time");
+                for stat in Stat::ALL {
+                    header.push(',');
+                    header.push_str(stat.name());
+                }
+                writeln!(file, "{}", header)
+                    .with_context(|| format!("writing {:?}", path))?;
+                Ok(Box::new(CsvSink { path: path.clone(), file }))
+            }
+            MonitorOutput::JsonLines(path) => {
+                let file = create_file(path)?;
+                Ok(Box::new(JsonLinesSink { path: path.clone(), file }))
+            }
+        }
+    }
+}
+
+fn create_file(
+    path: &std::path::Path,
+) -> Result<std::io::BufWriter<std::fs::File>, anyhow::Error> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("creating {:?}", path))?;
+    Ok(std::io::BufWriter::new(file))
+}
+
+/// Something that consumes samples collected by the monitor
+pub(crate) trait MonitorSink: Send {
+    /// Invoked when the monitor starts collecting stats (and when its
+    /// configuration changes)
+    fn start(&mut self, _config: &MonitorConfig) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Invoked for each sample collected
+    ///
+    /// `prev` is the previous sample, if one was collected since the monitor
+    /// was last started.
+    fn sample(
+        &mut self,
+        sample: &MonitorSample,
+        prev: Option<&MonitorSample>,
+        config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error>;
+}
+
+/// Prints a compact table of stats to stdout
+struct TerminalSink {
+    /// rows printed since the last header
+    nrows: usize,
+}
+
+impl TerminalSink {
+    fn print_header(&mut self, config: &MonitorConfig) {
+        let mut header: Vec<String> = Vec::new();
+        if config.timestamps {
+            header.push(format!("{:TIME_WIDTH$}", "TIME"));
+        }
+        header.extend(config.columns.iter().map(|c| {
+            format!("{:width$}", c.header(), width = column_width(c))
+        }));
+        header.extend(config.rates.iter().map(|c| {
+            format!("{:width$}", rate_header(c), width = rate_column_width(c))
+        }));
+        println!("{}", header.join(" "));
+        self.nrows = 0;
+    }
+}
+
+/// Width of the terminal table column for the sample time
+const TIME_WIDTH: usize = 14;
+
+/// Returns the width of the terminal table column for `stat`
+fn column_width(stat: &Stat) -> usize {
+    let value_width = match stat.kind() {
+        StatKind::Pressure => "desperate".len(),
+        StatKind::Bytes | StatKind::Counter => 5,
+    };
+    stat.header().len().max(value_width)
+}
+
+/// Returns the header of the terminal table column for the rate of `stat`
+fn rate_header(stat: &Stat) -> String {
+    format!("{}/s", stat.header())
+}
+
+/// Returns the width of the terminal table column for the rate of `stat`
+fn rate_column_width(stat: &Stat) -> usize {
+    rate_header(stat).len().max(7)
+}
+
+impl MonitorSink for TerminalSink {
+    fn start(&mut self, config: &MonitorConfig) -> Result<(), anyhow::Error> {
+        self.print_header(config);
+        Ok(())
+    }
+
+    fn sample(
+        &mut self,
+        sample: &MonitorSample,
+        prev: Option<&MonitorSample>,
+        config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(header_every) = config.header_every {
+            if self.nrows >= header_every {
+                self.print_header(config);
+            }
+        }
+
+        let mut row: Vec<String> = Vec::new();
+        if config.timestamps {
+            row.push(format!("{:TIME_WIDTH$.3}", sample.unix_time()));
+        }
+        row.extend(config.columns.iter().map(|c| {
+            let width = column_width(c);
+            let value = match c.value(sample) {
+                Some(value) => value,
+                None => return format!("{:>width$}", "-"),
+            };
+            match (c.kind(), prev) {
+                (StatKind::Bytes, _) => format!(
+                    "{:width$}",
+                    ByteSizeDisplayIn(ByteSize::b(value), config.units),
+                ),
+                // Counters are printed as the change since the last sample.
+                (StatKind::Counter, Some(prev)) => {
+                    match c.delta(sample, prev) {
+                        Some(delta) => format!("{:width$}", delta),
+                        None => format!("{:>width$}", "-"),
+                    }
+                }
+                (StatKind::Counter, None) => format!("{:>width$}", "-"),
+                (StatKind::Pressure, _) => match PressureLevel::from_u64(value)
+                {
+                    Some(level) => format!("{:width$}", level),
+                    None => format!("{:width$}", "?"),
+                },
+            }
+        }));
+        row.extend(config.rates.iter().map(|c| {
+            let width = rate_column_width(c);
+            // There's no rate for the first sample (or for stats that are
+            // unavailable).
+            let rate = match prev.and_then(|prev| c.rate(sample, prev)) {
+                Some(rate) => rate,
+                None => return format!("{:>width$}", "-"),
+            };
+            match c.kind() {
+                StatKind::Bytes => {
+                    format!("{:width$}", RateDisplayIn(rate, config.rate_units))
+                }
+                StatKind::Counter | StatKind::Pressure => {
+                    format!("{:width$.1}", rate)
+                }
+            }
+        }));
+        println!("{}", row.join(" "));
+        self.nrows += 1;
+        Ok(())
+    }
+}
+
+/// Writes one CSV row per sample to a file
+struct CsvSink {
+    path: PathBuf,
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+impl MonitorSink for CsvSink {
+    fn sample(
+        &mut self,
+        sample: &MonitorSample,
+        _prev: Option<&MonitorSample>,
+        _config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        let mut row = format!("{:.6}", sample.unix_time());
+        for stat in Stat::ALL {
+            row.push(',');
+            // Unavailable stats are left empty.
+            if let Some(value) = stat.value(sample) {
+                row.push_str(&value.to_string());
+            }
+        }
+        writeln!(self.file, "{}", row)
+            .and_then(|_| self.file.flush())
+            .with_context(|| format!("writing {:?}", self.path))
+    }
+}
+
+/// Writes one JSON object per sample to a file
+struct JsonLinesSink {
+    path: PathBuf,
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+impl MonitorSink for JsonLinesSink {
+    fn sample(
+        &mut self,
+        sample: &MonitorSample,
+        _prev: Option<&MonitorSample>,
+        _config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        let mut record = serde_json::Map::new();
+        record.insert(String::from("time"), sample.unix_time().into());
+        for stat in Stat::ALL {
+            // Unavailable stats are null.
+            record.insert(String::from(stat.name()), stat.value(sample).into());
+        }
+        serde_json::to_writer(&mut self.file, &record)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(self.file))
+            .and_then(|_| self.file.flush())
+            .with_context(|| format!("writing {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MonitorOutput;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn parse_err(s: &str) -> String {
+        MonitorOutput::from_str(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            MonitorOutput::from_str("terminal").unwrap(),
+            MonitorOutput::Terminal
+        );
+        assert_eq!(
+            MonitorOutput::from_str("csv=/tmp/out.csv").unwrap(),
+            MonitorOutput::Csv(PathBuf::from("/tmp/out.csv"))
+        );
+        // Only the first "=" separates the kind from the path.
+        assert_eq!(
+            MonitorOutput::from_str("jsonl=a=b.jsonl").unwrap(),
+            MonitorOutput::JsonLines(PathBuf::from("a=b.jsonl"))
+        );
+
+        for s in ["terminal", "csv=out.csv", "jsonl=out.jsonl"] {
+            assert_eq!(MonitorOutput::from_str(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_err("csv"),
+            "monitor output \"csv\": expected csv=FILE"
+        );
+        assert_eq!(
+            parse_err("jsonl="),
+            "monitor output \"jsonl=\": expected jsonl=FILE"
+        );
+        assert_eq!(
+            parse_err("xml=out.xml"),
+            "unsupported monitor output \"xml=out.xml\" (expected \
+            \"terminal\", \"csv=FILE\", or \"jsonl=FILE\")"
+        );
+        assert!(MonitorOutput::from_str("terminal=foo").is_err());
+        assert!(MonitorOutput::from_str("").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! How command output is formatted
+
+use anyhow::anyhow;
+use std::time::SystemTime;
+
+/// Format for the output of REPL commands
+///
+/// In `Json` mode, commands that support it print a single JSON object on one
+/// line instead of a human-readable table.  The objects are described in the
+/// README.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub const ALL: &'static [OutputFormat] =
+        &[OutputFormat::Text, OutputFormat::Json];
+
+    /// Returns the name used to select this format
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL.iter().find(|f| f.name() == s).copied().ok_or_else(
+            || {
+                anyhow!(
+                    "unsupported output format {:?} (expected \"text\" or \
+                    \"json\")",
+                    s
+                )
+            },
+        )
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Formats `time` as a UTC time of day with millisecond resolution (e.g.,
+/// `18:04:05.123Z`)
+pub fn format_time_of_day(time: SystemTime) -> String {
+    let since_epoch =
+        time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}Z",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_time_of_day;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_format_time_of_day() {
+        let time = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(86400 * 365 + 18 * 3600 + 4 * 60 + 5)
+            + Duration::from_millis(123);
+        assert_eq!(format_time_of_day(time), "18:04:05.123Z");
+    }
+}
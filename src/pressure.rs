@@ -0,0 +1,113 @@
+//! Classifying memory pressure
+//!
+//! The kernel's pageout behavior changes as freemem drops below each of three
+//! thresholds: below lotsfree, the pageout scanner starts; below desfree, the
+//! system begins swapping; and below minfree, allocations may block.  This
+//! module summarizes where freemem is relative to those thresholds.
+
+use crate::kstat::PhysicalMemoryStats;
+use bytesize::ByteSize;
+
+/// How much pressure the system is under, from least to most
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PressureLevel {
+    /// freemem is above lotsfree
+    Plenty,
+    /// freemem is at or below lotsfree (the pageout scanner is running)
+    Low,
+    /// freemem is at or below desfree (the system may swap)
+    Desperate,
+    /// freemem is at or below minfree (allocations may block)
+    Critical,
+}
+
+impl PressureLevel {
+    pub const ALL: &'static [PressureLevel] = &[
+        PressureLevel::Plenty,
+        PressureLevel::Low,
+        PressureLevel::Desperate,
+        PressureLevel::Critical,
+    ];
+
+    /// Returns the name of this level
+    pub fn name(&self) -> &'static str {
+        match self {
+            PressureLevel::Plenty => "plenty",
+            PressureLevel::Low => "low",
+            PressureLevel::Desperate => "desperate",
+            PressureLevel::Critical => "critical",
+        }
+    }
+
+    /// Returns the level whose number is `value` (see [`Stat::Pressure`])
+    ///
+    /// [`Stat::Pressure`]: crate::stats::Stat::Pressure
+    pub fn from_u64(value: u64) -> Option<PressureLevel> {
+        PressureLevel::ALL.get(usize::try_from(value).ok()?).copied()
+    }
+}
+
+impl std::fmt::Display for PressureLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Describes freemem relative to the pageout thresholds
+#[derive(Debug)]
+pub struct MemoryPressure {
+    pub level: PressureLevel,
+    pub freemem: ByteSize,
+    pub lotsfree: ByteSize,
+    pub desfree: ByteSize,
+    pub minfree: ByteSize,
+}
+
+impl MemoryPressure {
+    pub(crate) fn from_physmem(physmem: &PhysicalMemoryStats) -> Self {
+        let freemem = physmem.freemem;
+        let level = if freemem > physmem.lotsfree {
+            PressureLevel::Plenty
+        } else if freemem > physmem.desfree {
+            PressureLevel::Low
+        } else if freemem > physmem.minfree {
+            PressureLevel::Desperate
+        } else {
+            PressureLevel::Critical
+        };
+
+        MemoryPressure {
+            level,
+            freemem,
+            lotsfree: physmem.lotsfree,
+            desfree: physmem.desfree,
+            minfree: physmem.minfree,
+        }
+    }
+
+    /// Returns the thresholds, from highest to lowest, along with the level
+    /// that the system enters when freemem drops to each one
+    pub fn thresholds(&self) -> [(&'static str, ByteSize, PressureLevel); 3] {
+        [
+            ("lotsfree", self.lotsfree, PressureLevel::Low),
+            ("desfree", self.desfree, PressureLevel::Desperate),
+            ("minfree", self.minfree, PressureLevel::Critical),
+        ]
+    }
+
+    /// Returns the next threshold below the current level and how much more
+    /// freemem would have to drop to reach it (or `None` if already critical)
+    pub fn next_threshold(&self) -> Option<(&'static str, ByteSize)> {
+        self.thresholds()
+            .into_iter()
+            .find(|(_, _, level)| *level > self.level)
+            .map(|(name, value, _)| {
+                (
+                    name,
+                    ByteSize::b(
+                        self.freemem.as_u64().saturating_sub(value.as_u64()),
+                    ),
+                )
+            })
+    }
+}
@@ -0,0 +1,329 @@
+//! Line editing for the REPL
+//!
+//! The REPL reads lines itself (rather than leaving that to
+//! `reedline_repl_rs::Repl`) so that it can handle output redirection (see
+//! [`crate::command_line`]) before the command's arguments are parsed.  `Repl`
+//! has no hook for that: it splits each line and hands the words straight to
+//! clap (which rejects `> FILE` as unexpected arguments), its callbacks are
+//! plain function pointers, and it prints each command's output itself.  Its
+//! prompt, completer, and help are private, too, so this module provides
+//! equivalents of those.
+
+use anyhow::anyhow;
+use reedline_repl_rs::clap::Command;
+use reedline_repl_rs::crossterm::event::KeyCode;
+use reedline_repl_rs::crossterm::event::KeyModifiers;
+use reedline_repl_rs::nu_ansi_term::Color;
+use reedline_repl_rs::nu_ansi_term::Style;
+use reedline_repl_rs::paint_green_bold;
+use reedline_repl_rs::paint_yellow_bold;
+use reedline_repl_rs::reedline::default_emacs_keybindings;
+use reedline_repl_rs::reedline::ColumnarMenu;
+use reedline_repl_rs::reedline::Completer;
+use reedline_repl_rs::reedline::DefaultHinter;
+use reedline_repl_rs::reedline::DefaultPrompt;
+use reedline_repl_rs::reedline::DefaultValidator;
+use reedline_repl_rs::reedline::Emacs;
+use reedline_repl_rs::reedline::ExampleHighlighter;
+use reedline_repl_rs::reedline::Prompt;
+use reedline_repl_rs::reedline::PromptEditMode;
+use reedline_repl_rs::reedline::PromptHistorySearch;
+use reedline_repl_rs::reedline::Reedline;
+use reedline_repl_rs::reedline::ReedlineEvent;
+use reedline_repl_rs::reedline::ReedlineMenu;
+use reedline_repl_rs::reedline::Span;
+use reedline_repl_rs::reedline::Suggestion;
+use std::borrow::Cow;
+
+/// Name of the completion menu that Tab opens
+const COMPLETION_MENU: &str = "completion_menu";
+
+/// Returns a line editor that completes and highlights the names (and
+/// arguments) of `commands`
+pub fn line_editor(commands: &[Command<'static>]) -> Reedline {
+    let mut names: Vec<String> =
+        commands.iter().map(|c| c.get_name().to_string()).collect();
+    names.push(String::from("help"));
+
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::Menu(COMPLETION_MENU.to_string()),
+    );
+    let completer = CommandCompleter { commands: commands.to_vec() };
+    let menu = ColumnarMenu::default().with_name(COMPLETION_MENU);
+    let hint_style = Style::new().italic().fg(Color::LightGray);
+    Reedline::create()
+        .with_edit_mode(Box::new(Emacs::new(keybindings)))
+        .with_completer(Box::new(completer))
+        .with_menu(ReedlineMenu::EngineCompleter(Box::new(menu)))
+        .with_highlighter(Box::new(ExampleHighlighter::new(names)))
+        .with_validator(Box::new(DefaultValidator))
+        .with_hinter(Box::new(DefaultHinter::default().with_style(hint_style)))
+        .with_partial_completions(false)
+        .with_quick_completions(true)
+}
+
+/// Returns the text printed by the `help` command: a summary of all
+/// `commands` or, if `args` names one of them, that command's usage
+pub fn help(
+    name: &str,
+    description: &str,
+    commands: &[Command<'static>],
+    args: &[String],
+) -> Result<String, anyhow::Error> {
+    let mut help = Vec::new();
+    match args.first() {
+        None => {
+            let mut app = Command::new(name);
+            for command in commands {
+                app = app.subcommand(command.clone());
+            }
+            app.write_help(&mut help)?;
+            let mut help = String::from_utf8_lossy(&help).into_owned();
+            let marker = "SUBCOMMANDS:";
+            if let Some(i) = help.find(marker) {
+                help =
+                    paint_yellow_bold("COMMANDS:") + &help[i + marker.len()..];
+            }
+            Ok(format!(
+                "{}\n{}\n\n{}",
+                paint_green_bold(name),
+                description,
+                help
+            ))
+        }
+        Some(command_name) => {
+            let mut command = commands
+                .iter()
+                .find(|c| c.get_name() == command_name)
+                .ok_or_else(|| {
+                    anyhow!("no help for unknown command {:?}", command_name)
+                })?
+                .clone();
+            command.write_help(&mut help)?;
+            Ok(String::from_utf8_lossy(&help).into_owned())
+        }
+    }
+}
+
+/// The REPL's prompt: the given text on the left and the defaults for
+/// everything else
+#[derive(Clone)]
+pub struct SwappyPrompt {
+    prefix: String,
+    default: DefaultPrompt,
+}
+
+impl SwappyPrompt {
+    pub fn new(name: &str) -> SwappyPrompt {
+        SwappyPrompt {
+            prefix: paint_green_bold(&format!("{}> ", name)),
+            default: DefaultPrompt,
+        }
+    }
+}
+
+impl Prompt for SwappyPrompt {
+    fn render_prompt_left(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.prefix)
+    }
+
+    fn render_prompt_right(&self) -> Cow<'_, str> {
+        self.default.render_prompt_right()
+    }
+
+    fn render_prompt_indicator(
+        &self,
+        edit_mode: PromptEditMode,
+    ) -> Cow<'_, str> {
+        self.default.render_prompt_indicator(edit_mode)
+    }
+
+    fn render_prompt_multiline_indicator(&self) -> Cow<'_, str> {
+        self.default.render_prompt_multiline_indicator()
+    }
+
+    fn render_prompt_history_search_indicator(
+        &self,
+        history_search: PromptHistorySearch,
+    ) -> Cow<'_, str> {
+        self.default.render_prompt_history_search_indicator(history_search)
+    }
+}
+
+/// Completes command names and, after a command name, that command's flags
+/// and possible argument values
+struct CommandCompleter {
+    commands: Vec<Command<'static>>,
+}
+
+impl Completer for CommandCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let line = &line[..pos];
+        let word_start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word = &line[word_start..];
+        let span = Span::new(word_start, pos);
+
+        let mut suggestions: Vec<Suggestion> = if word_start == 0 {
+            self.commands
+                .iter()
+                .map(|c| (c.get_name().to_string(), c.get_about()))
+                .chain(std::iter::once((
+                    String::from("help"),
+                    Some("show help"),
+                )))
+                .filter(|(name, _)| name.starts_with(word))
+                .map(|(name, help)| suggestion(name, help, span))
+                .collect()
+        } else {
+            let name = line.split(' ').next().unwrap_or("");
+            match self.commands.iter().find(|c| c.get_name() == name) {
+                Some(command) => argument_suggestions(command, word, span),
+                None => Vec::new(),
+            }
+        };
+        suggestions.dedup();
+        suggestions
+    }
+}
+
+/// Returns suggestions for the flags and possible values of `command`'s
+/// arguments that start with `word`
+fn argument_suggestions(
+    command: &Command<'static>,
+    word: &str,
+    span: Span,
+) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    // Global arguments are --help and --version.
+    for arg in command.get_arguments().filter(|a| !a.is_global_set()) {
+        if let Some(values) = arg.get_possible_values() {
+            suggestions.extend(
+                values.iter().filter(|v| v.get_name().starts_with(word)).map(
+                    |v| {
+                        suggestion(v.get_name().to_string(), v.get_help(), span)
+                    },
+                ),
+            );
+        }
+        let flags = arg
+            .get_long()
+            .map(|long| format!("--{}", long))
+            .into_iter()
+            .chain(arg.get_short().map(|short| format!("-{}", short)));
+        for flag in flags.filter(|f| f.starts_with(word)) {
+            suggestions.push(suggestion(flag, arg.get_help(), span));
+        }
+    }
+    suggestions
+}
+
+fn suggestion(value: String, help: Option<&str>, span: Span) -> Suggestion {
+    Suggestion {
+        value,
+        description: help.map(|h| h.to_string()),
+        extra: None,
+        span,
+        append_whitespace: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::help;
+    use super::CommandCompleter;
+    use super::SwappyPrompt;
+    use reedline_repl_rs::clap::Arg;
+    use reedline_repl_rs::clap::Command;
+    use reedline_repl_rs::reedline::Completer;
+    use reedline_repl_rs::reedline::Prompt;
+    use reedline_repl_rs::reedline::Span;
+
+    fn commands() -> Vec<Command<'static>> {
+        vec![
+            Command::new("swap-info").about("Show swap devices"),
+            Command::new("swap-reserve")
+                .about("Reserve swap")
+                .arg(Arg::new("size").required(true).help("bytes to reserve")),
+            Command::new("set").arg(
+                Arg::new("format")
+                    .long("format")
+                    .short('f')
+                    .takes_value(true)
+                    .possible_values(["text", "json"]),
+            ),
+        ]
+    }
+
+    fn complete(line: &str) -> Vec<(String, Span)> {
+        let mut completer = CommandCompleter { commands: commands() };
+        completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| (s.value, s.span))
+            .collect()
+    }
+
+    fn values(line: &str) -> Vec<String> {
+        complete(line).into_iter().map(|(value, _)| value).collect()
+    }
+
+    #[test]
+    fn test_complete_names() {
+        assert_eq!(values("swap-"), ["swap-info", "swap-reserve"]);
+        assert_eq!(values("he"), ["help"]);
+        assert_eq!(values("").len(), 4);
+        assert!(values("bogus").is_empty());
+        assert_eq!(
+            complete("swap-i"),
+            [(String::from("swap-info"), Span::new(0, 6))]
+        );
+    }
+
+    #[test]
+    fn test_complete_arguments() {
+        assert_eq!(values("set "), ["text", "json", "--format", "-f"]);
+        assert_eq!(values("set j"), ["json"]);
+        assert_eq!(
+            complete("set --f"),
+            [(String::from("--format"), Span::new(4, 7))]
+        );
+        // Positional arguments without possible values have nothing to
+        // suggest, and --help isn't worth suggesting.
+        assert!(values("swap-reserve ").is_empty());
+        assert!(values("swap-info --h").is_empty());
+        assert!(values("bogus --").is_empty());
+        // Only the text before the cursor counts.
+        let mut completer = CommandCompleter { commands: commands() };
+        let suggestions = completer.complete("set j --verbose", 5);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].value, "json");
+    }
+
+    #[test]
+    fn test_help() {
+        let summary = help("swappy", "does things", &commands(), &[]).unwrap();
+        assert!(summary.contains("does things"));
+        assert!(summary.contains("COMMANDS:"));
+        assert!(!summary.contains("SUBCOMMANDS:"));
+        assert!(summary.contains("swap-reserve"));
+        assert!(summary.contains("Show swap devices"));
+
+        let args = [String::from("swap-reserve")];
+        let usage = help("swappy", "does things", &commands(), &args).unwrap();
+        assert!(usage.contains("<size>"));
+        assert!(usage.contains("bytes to reserve"));
+
+        let args = [String::from("bogus")];
+        let error = help("swappy", "", &commands(), &args).unwrap_err();
+        assert_eq!(error.to_string(), "no help for unknown command \"bogus\"");
+    }
+
+    #[test]
+    fn test_prompt() {
+        let prompt = SwappyPrompt::new("swappy");
+        assert!(prompt.render_prompt_left().contains("swappy> "));
+    }
+}
@@ -0,0 +1,59 @@
+//! Reports which pages of a mapping are resident in physical memory
+
+use crate::PAGE_SIZE;
+use anyhow::Context;
+use bytesize::ByteSize;
+
+/// Describes how much of a mapping is resident in physical memory
+#[derive(Debug)]
+pub struct Residency {
+    /// number of pages that are resident
+    pub resident_pages: u64,
+    /// total number of pages in the mapping
+    pub total_pages: u64,
+}
+
+impl Residency {
+    /// Uses `mincore(2)` to determine which pages in the range `[addr, addr +
+    /// size)` are resident
+    ///
+    /// Pages that were never touched are not resident, nor are pages that have
+    /// been paged out to swap.
+    pub fn fetch(addr: usize, size: usize) -> Result<Residency, anyhow::Error> {
+        let npages = size.div_ceil(PAGE_SIZE);
+        let mut vec: Vec<u8> = vec![0; npages];
+        // The argument types differ between platforms (e.g., `addr` is a
+        // `caddr_t` on illumos), so let them be inferred.
+        let r = unsafe {
+            libc::mincore(addr as *mut _, size, vec.as_mut_ptr().cast())
+        };
+        if r != 0 {
+            return Err(std::io::Error::last_os_error()).context("mincore");
+        }
+
+        let resident_pages =
+            vec.iter().filter(|v| **v & 0x1 != 0).count() as u64;
+        Ok(Residency { resident_pages, total_pages: npages as u64 })
+    }
+
+    /// Returns the number of bytes that are resident
+    pub fn resident(&self) -> ByteSize {
+        ByteSize::b(self.resident_pages * (PAGE_SIZE as u64))
+    }
+
+    /// Returns the number of bytes that are not resident
+    pub fn nonresident(&self) -> ByteSize {
+        ByteSize::b(
+            (self.total_pages - self.resident_pages) * (PAGE_SIZE as u64),
+        )
+    }
+
+    /// Returns the percentage of pages that are resident
+    pub fn resident_pct(&self) -> f64 {
+        if self.total_pages == 0 {
+            0.0
+        } else {
+            100.0 * (self.resident_pages as f64) / (self.total_pages as f64)
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Actions scheduled to run later, once or repeatedly
+//!
+//! The REPL's `at` and `every` commands schedule commands to run in the
+//! future while the user keeps typing.  The [`Scheduler`] runs each action on
+//! its own thread at the appropriate time.  Actions run one at a time, so a
+//! long-running action delays any others that come due while it runs.
+
+use crate::interrupt;
+use anyhow::anyhow;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+type Action = Box<dyn FnMut() + Send>;
+
+/// Describes one scheduled action, as reported by [`Scheduler::list()`]
+#[derive(Clone, Debug)]
+pub struct ScheduledStatus {
+    pub id: usize,
+    pub description: String,
+    /// how long until the action next runs
+    pub next_in: Duration,
+    /// for repeating actions, the time between runs
+    pub every: Option<Duration>,
+    /// number of times the action has run so far
+    pub nruns: u64,
+}
+
+struct Scheduled {
+    id: usize,
+    description: String,
+    next: Instant,
+    every: Option<Duration>,
+    nruns: u64,
+    /// `None` while the action is running
+    action: Option<Action>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    scheduled: Vec<Scheduled>,
+    next_id: usize,
+}
+
+/// Runs actions at scheduled times on a dedicated thread
+pub(crate) struct Scheduler {
+    state: Arc<(Mutex<SchedulerState>, Condvar)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        let state = Arc::new((
+            Mutex::new(SchedulerState { scheduled: Vec::new(), next_id: 1 }),
+            Condvar::new(),
+        ));
+        let thread_state = Arc::clone(&state);
+        std::thread::spawn(move || scheduler_thread(&thread_state));
+        Scheduler { state }
+    }
+
+    /// Schedules `action` to run after `delay` and then (if `every` is
+    /// specified) repeatedly at that interval, returning the action's id
+    pub fn add(
+        &self,
+        description: String,
+        delay: Duration,
+        every: Option<Duration>,
+        action: Action,
+    ) -> usize {
+        let (lock, cv) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.scheduled.push(Scheduled {
+            id,
+            description,
+            next: Instant::now() + delay,
+            every,
+            nruns: 0,
+            action: Some(action),
+        });
+        cv.notify_all();
+        id
+    }
+
+    /// Returns the actions that are still scheduled, soonest first
+    pub fn list(&self) -> Vec<ScheduledStatus> {
+        let (lock, _) = &*self.state;
+        let state = lock.lock().unwrap();
+        let now = Instant::now();
+        let mut rv: Vec<ScheduledStatus> = state
+            .scheduled
+            .iter()
+            .map(|s| ScheduledStatus {
+                id: s.id,
+                description: s.description.clone(),
+                next_in: s.next.saturating_duration_since(now),
+                every: s.every,
+                nruns: s.nruns,
+            })
+            .collect();
+        rv.sort_by_key(|s| s.next_in);
+        rv
+    }
+
+    /// Cancels the action with id `id`
+    ///
+    /// If the action is running right now, that run finishes, but it won't
+    /// run again.
+    pub fn cancel(&self, id: usize) -> Result<(), anyhow::Error> {
+        let (lock, _) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let nbefore = state.scheduled.len();
+        state.scheduled.retain(|s| s.id != id);
+        if state.scheduled.len() == nbefore {
+            return Err(anyhow!("no scheduled command with id {}", id));
+        }
+        Ok(())
+    }
+}
+
+fn scheduler_thread(state: &(Mutex<SchedulerState>, Condvar)) {
+    // Ctrl-C is meant for the REPL, not for whatever action happens to be
+    // running.
+    interrupt::set_background();
+    let (lock, cv) = state;
+    let mut guard = lock.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let due = guard
+            .scheduled
+            .iter_mut()
+            .filter(|s| s.action.is_some())
+            .min_by_key(|s| s.next);
+        let (id, mut action) = match due {
+            None => {
+                guard = cv.wait(guard).unwrap();
+                continue;
+            }
+            Some(s) if s.next > now => {
+                let timeout = s.next - now;
+                guard = cv.wait_timeout(guard, timeout).unwrap().0;
+                continue;
+            }
+            Some(s) => (s.id, s.action.take().unwrap()),
+        };
+
+        // Run the action without holding the lock so that the REPL can list,
+        // add, and cancel actions (and so that the action itself can, too).
+        drop(guard);
+        action();
+        guard = lock.lock().unwrap();
+
+        // The action may have been cancelled while it was running.
+        if let Some(i) = guard.scheduled.iter().position(|s| s.id == id) {
+            let scheduled = &mut guard.scheduled[i];
+            scheduled.nruns += 1;
+            match scheduled.every {
+                None => {
+                    guard.scheduled.remove(i);
+                }
+                Some(every) => {
+                    // If we've fallen behind (e.g., because the action takes
+                    // longer than the interval), skip the missed runs rather
+                    // than running it back-to-back.
+                    scheduled.next += every;
+                    if scheduled.next < Instant::now() {
+                        scheduled.next = Instant::now() + every;
+                    }
+                    scheduled.action = Some(action);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Expectations checked by command scripts
+//!
+//! Besides ordinary commands, scripts (see the REPL's `source` command) can
+//! contain `expect` lines that check the value of a stat, like:
+//!
+//! ```text
+//! expect swap.reserved delta ~= 10GiB ± 1%
+//! expect freemem > lotsfree
+//! ```
+//!
+//! With `delta`, the stat's change since just before the previous command is
+//! checked instead of its current value.  `~=` checks that the value is
+//! within a tolerance (1% by default) of the expected value.  The other
+//! comparisons are the same as for alerts (see [`crate::alert`]).
+
+use crate::alert::Comparison;
+use crate::alert::Operand;
+use crate::monitor::MonitorSample;
+use crate::pressure::PressureLevel;
+use crate::stats::Stat;
+use crate::stats::StatKind;
+use anyhow::anyhow;
+use anyhow::bail;
+use bytesize::ByteSize;
+use std::str::FromStr;
+
+/// Tolerance used for `~=` when none is given
+const DEFAULT_TOLERANCE_PERCENT: f64 = 1.0;
+
+/// How the actual value of an [`Expectation`] is compared to the expected one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpectOp {
+    /// the actual value is within the tolerance of the expected value
+    Approx(Tolerance),
+    Compare(Comparison),
+}
+
+/// How far the actual value may be from the expected one for `~=`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tolerance {
+    /// a percentage of the expected value
+    Percent(f64),
+    /// an absolute amount (in bytes, for byte-valued stats)
+    Value(u64),
+}
+
+impl Tolerance {
+    /// Returns the largest allowed difference from `expected`
+    fn allowed(&self, expected: i64) -> u64 {
+        match self {
+            Tolerance::Percent(percent) => {
+                ((expected.unsigned_abs() as f64) * percent / 100.0) as u64
+            }
+            Tolerance::Value(value) => *value,
+        }
+    }
+}
+
+impl FromStr for Tolerance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = f64::from_str(percent.trim())
+                .map_err(|e| anyhow!("tolerance {:?}: {}", s, e))?;
+            return Ok(Tolerance::Percent(percent));
+        }
+        ByteSize::from_str(s).map(|b| Tolerance::Value(b.as_u64())).map_err(
+            |_| anyhow!("tolerance {:?}: expected a size or a percentage", s),
+        )
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tolerance::Percent(percent) => write!(f, "{}%", percent),
+            Tolerance::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A check on the value of one stat
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expectation {
+    pub stat: Stat,
+    /// whether to check the change since the baseline sample rather than the
+    /// current value
+    pub delta: bool,
+    pub op: ExpectOp,
+    pub expected: Operand,
+    /// whether the expected value is negated (e.g., `-10GiB`)
+    pub negative: bool,
+}
+
+impl Expectation {
+    /// Checks this expectation against `sample`, returning an error that
+    /// describes the expected and actual values if it doesn't hold
+    ///
+    /// `baseline` is the sample taken before the previous command.  It's
+    /// required for `delta` expectations.
+    pub fn check(
+        &self,
+        sample: &MonitorSample,
+        baseline: Option<&MonitorSample>,
+    ) -> Result<(), anyhow::Error> {
+        let actual = if self.delta {
+            let baseline = baseline.ok_or_else(|| {
+                anyhow!(
+                    "expect {}: no stats were collected before the \
+                    previous command",
+                    self
+                )
+            })?;
+            self.stat.delta(sample, baseline)
+        } else {
+            self.stat.value(sample).map(|value| value as i64)
+        };
+        let unavailable = |stat: &dyn std::fmt::Display| {
+            anyhow!("expect {}: stat {} is not available", self, stat)
+        };
+        let actual = actual.ok_or_else(|| unavailable(&self.stat))?;
+        let expected = {
+            let value = self
+                .expected
+                .value(sample)
+                .ok_or_else(|| unavailable(&self.expected))?
+                as i64;
+            if self.negative {
+                -value
+            } else {
+                value
+            }
+        };
+
+        let (ok, expected_str) = match self.op {
+            ExpectOp::Approx(tolerance) => {
+                let allowed = tolerance.allowed(expected);
+                (
+                    actual.abs_diff(expected) <= allowed,
+                    format!(
+                        "{} ± {}",
+                        self.format_value(expected),
+                        self.format_value(allowed as i64)
+                            .trim_start_matches('+')
+                    ),
+                )
+            }
+            ExpectOp::Compare(op) => (
+                op.compare(actual, expected),
+                format!("{} {}", op.symbol(), self.format_value(expected)),
+            ),
+        };
+        if ok {
+            return Ok(());
+        }
+
+        bail!(
+            "expectation failed: {}\n    expected: {}\n      actual: {}\n\
+            \x20 difference: {}",
+            self,
+            expected_str,
+            self.format_value(actual),
+            self.format_value_signed(actual - expected),
+        );
+    }
+
+    /// Formats a value of this expectation's stat for an error message
+    fn format_value(&self, value: i64) -> String {
+        if self.delta {
+            self.format_value_signed(value)
+        } else if self.stat.kind() == StatKind::Pressure {
+            match u64::try_from(value).ok().and_then(PressureLevel::from_u64) {
+                Some(level) => level.to_string(),
+                None => value.to_string(),
+            }
+        } else {
+            self.format_value_signed(value).trim_start_matches('+').to_string()
+        }
+    }
+
+    /// Formats a value of this expectation's stat with an explicit sign
+    fn format_value_signed(&self, value: i64) -> String {
+        let sign = if value < 0 { "-" } else { "+" };
+        match self.stat.kind() {
+            StatKind::Bytes => format!(
+                "{}{}",
+                sign,
+                ByteSize::b(value.unsigned_abs()).to_string_as(true)
+            ),
+            StatKind::Counter | StatKind::Pressure => {
+                format!("{}{}", sign, value.unsigned_abs())
+            }
+        }
+    }
+}
+
+impl FromStr for Expectation {
+    type Err = anyhow::Error;
+
+    /// Parses an expectation like `swap.reserved delta ~= 10GiB ± 1%` (the
+    /// part of an `expect` line after "expect")
+    ///
+    /// Stat names may use "." in place of "-" (e.g., `swap.reserved`).  The
+    /// tolerance may also be written with "+-".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (op_str, index) = std::iter::once("~=")
+            .chain(Comparison::ALL.iter().map(|op| op.symbol()))
+            .find_map(|op| s.find(op).map(|i| (op, i)))
+            .ok_or_else(|| {
+                anyhow!(
+                    "expect {:?}: expected a comparison (~=, <, <=, >, >=, ==, \
+                    or !=)",
+                    s
+                )
+            })?;
+        let lhs_str = s[..index].trim();
+        let rhs_str = s[index + op_str.len()..].trim();
+
+        let mut lhs_words = lhs_str.split_whitespace();
+        let stat_str = lhs_words
+            .next()
+            .ok_or_else(|| anyhow!("expect {:?}: expected a stat name", s))?;
+        let stat = Stat::from_str(&stat_str.replace('.', "-"))?;
+        let delta = match lhs_words.next() {
+            None => false,
+            Some("delta") => true,
+            Some(word) => {
+                bail!("expect {:?}: expected \"delta\", found {:?}", s, word)
+            }
+        };
+        if let Some(word) = lhs_words.next() {
+            bail!("expect {:?}: unexpected {:?}", s, word);
+        }
+
+        let (value_str, tolerance_str) = match ["±", "+-", "+/-"]
+            .iter()
+            .find_map(|sep| rhs_str.split_once(sep))
+        {
+            Some((value, tolerance)) => (value.trim(), Some(tolerance.trim())),
+            None => (rhs_str, None),
+        };
+        let (negative, value_str) = match value_str.strip_prefix('-') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, value_str.trim_start_matches('+')),
+        };
+        if value_str.is_empty() {
+            bail!("expect {:?}: expected a value after {:?}", s, op_str);
+        }
+        let expected = Operand::from_str(value_str)?;
+
+        let op = if op_str == "~=" {
+            ExpectOp::Approx(match tolerance_str {
+                Some(t) => Tolerance::from_str(t)?,
+                None => Tolerance::Percent(DEFAULT_TOLERANCE_PERCENT),
+            })
+        } else if tolerance_str.is_some() {
+            bail!("expect {:?}: a tolerance can only be used with \"~=\"", s);
+        } else {
+            // This can't fail because `op_str` came from `Comparison::ALL`.
+            let op = Comparison::ALL
+                .iter()
+                .find(|op| op.symbol() == op_str)
+                .unwrap();
+            ExpectOp::Compare(*op)
+        };
+
+        Ok(Expectation { stat, delta, op, expected, negative })
+    }
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.stat)?;
+        if self.delta {
+            write!(f, " delta")?;
+        }
+        let sign = if self.negative { "-" } else { "" };
+        match self.op {
+            ExpectOp::Approx(tolerance) => {
+                write!(f, " ~= {}{} ± {}", sign, self.expected, tolerance)
+            }
+            ExpectOp::Compare(op) => {
+                write!(f, " {} {}{}", op.symbol(), sign, self.expected)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExpectOp;
+    use super::Expectation;
+    use super::Tolerance;
+    use crate::alert::Comparison;
+    use crate::alert::Operand;
+    use crate::pressure::PressureLevel;
+    use crate::stats::Stat;
+    use std::str::FromStr;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn parse_err(s: &str) -> String {
+        Expectation::from_str(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_tolerance() {
+        assert_eq!(Tolerance::from_str("1%").unwrap(), Tolerance::Percent(1.0));
+        assert_eq!(
+            Tolerance::from_str("2.5 %").unwrap(),
+            Tolerance::Percent(2.5)
+        );
+        assert_eq!(Tolerance::from_str("1GiB").unwrap(), Tolerance::Value(GIB));
+        assert_eq!(
+            Tolerance::from_str("4096").unwrap(),
+            Tolerance::Value(4096)
+        );
+        assert_eq!(
+            Tolerance::from_str("lots").unwrap_err().to_string(),
+            "tolerance \"lots\": expected a size or a percentage"
+        );
+        assert!(Tolerance::from_str("x%").is_err());
+
+        assert_eq!(Tolerance::Percent(1.0).allowed(1000), 10);
+        assert_eq!(Tolerance::Percent(1.0).allowed(-1000), 10);
+        assert_eq!(Tolerance::Value(5).allowed(1000), 5);
+        assert_eq!(Tolerance::Percent(2.5).to_string(), "2.5%");
+    }
+
+    #[test]
+    fn test_parse() {
+        let e =
+            Expectation::from_str("swap.reserved delta ~= 10GiB ± 2%").unwrap();
+        assert_eq!(
+            e,
+            Expectation {
+                stat: Stat::SwapReserved,
+                delta: true,
+                op: ExpectOp::Approx(Tolerance::Percent(2.0)),
+                expected: Operand::Value(10 * GIB),
+                negative: false,
+            }
+        );
+
+        let e = Expectation::from_str("freemem > lotsfree").unwrap();
+        assert_eq!(e.stat, Stat::Freemem);
+        assert!(!e.delta);
+        assert_eq!(e.op, ExpectOp::Compare(Comparison::Gt));
+        assert_eq!(e.expected, Operand::Stat(Stat::Lotsfree));
+        assert_eq!(e.to_string(), "freemem > lotsfree");
+
+        let e = Expectation::from_str("freemem>=1GiB").unwrap();
+        assert_eq!(e.op, ExpectOp::Compare(Comparison::Ge));
+        assert_eq!(e.expected, Operand::Value(GIB));
+
+        // The default tolerance is 1%, and "+-" works in place of "±".
+        let e = Expectation::from_str("swap-reserved ~= 1GiB").unwrap();
+        assert_eq!(e.op, ExpectOp::Approx(Tolerance::Percent(1.0)));
+        let e = Expectation::from_str("swap-reserved delta ~= -1GiB +- 1MiB")
+            .unwrap();
+        assert!(e.negative);
+        assert_eq!(e.expected, Operand::Value(GIB));
+        assert_eq!(e.op, ExpectOp::Approx(Tolerance::Value(1024 * 1024)));
+        assert_eq!(
+            e.to_string(),
+            "swap-reserved delta ~= -1073741824 ± 1048576"
+        );
+
+        let e = Expectation::from_str("pressure != desperate").unwrap();
+        assert_eq!(e.expected, Operand::Value(PressureLevel::Desperate as u64));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_err("freemem"),
+            "expect \"freemem\": expected a comparison (~=, <, <=, >, >=, \
+            ==, or !=)"
+        );
+        assert_eq!(
+            parse_err("~= 1GiB"),
+            "expect \"~= 1GiB\": expected a stat name"
+        );
+        assert_eq!(
+            parse_err("freemem later > 1"),
+            "expect \"freemem later > 1\": expected \"delta\", found \"later\""
+        );
+        assert_eq!(
+            parse_err("freemem delta now > 1"),
+            "expect \"freemem delta now > 1\": unexpected \"now\""
+        );
+        assert_eq!(
+            parse_err("freemem > "),
+            "expect \"freemem > \": expected a value after \">\""
+        );
+        assert_eq!(
+            parse_err("freemem > 1GiB ± 1%"),
+            "expect \"freemem > 1GiB ± 1%\": a tolerance can only be used \
+            with \"~=\""
+        );
+        assert!(Expectation::from_str("bogus > 1").is_err());
+        assert!(Expectation::from_str("freemem > bogus").is_err());
+        assert!(Expectation::from_str("freemem ~= 1GiB ± bogus").is_err());
+    }
+}
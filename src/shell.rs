@@ -0,0 +1,214 @@
+//! Running shell commands on swappy's behalf
+//!
+//! Swappy may have many gigabytes of swap reserved at any given time.  Forking
+//! swappy to run a shell command would require reserving all of that again
+//! for the child, which is likely to fail (or to perturb whatever we're
+//! measuring), even though the child is about to exec.  Instead, swappy starts
+//! a small helper process at startup, before it has created any mappings.
+//! When swappy wants to run a shell command, it sends the command (and the
+//! data to feed to its stdin) to the helper, which forks and execs the shell.
+//!
+//! The helper is swappy itself, run with [`HELPER_ARG`] as its only argument
+//! (see [`helper_main()`] and [`crate::swappy::Swappy::start_shell_helper()`]).
+//! Requests are sent on its stdin as a header line `COMMAND_LEN INPUT_LEN`
+//! followed by the command and the input.  The helper writes back the shell's
+//! exit status (or the negated signal number, if it was killed) as one line
+//! on file descriptor 3.  The shell inherits the helper's stdout and stderr,
+//! which are swappy's.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use std::io::BufRead;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+
+/// Argument that tells swappy to run as the shell helper
+pub const HELPER_ARG: &str = "--shell-helper";
+
+/// File descriptor on which the helper writes responses
+const RESPONSE_FD: libc::c_int = 3;
+
+/// Handle to the shell helper process
+pub(crate) struct ShellHelper {
+    child: std::process::Child,
+    requests: std::process::ChildStdin,
+    responses: std::io::BufReader<std::fs::File>,
+}
+
+impl ShellHelper {
+    /// Starts the helper process
+    ///
+    /// This should be called before creating any large mappings.
+    pub fn start() -> Result<ShellHelper, anyhow::Error> {
+        let exe =
+            std::env::current_exe().context("locating swappy executable")?;
+        let (response_read, response_write) = cloexec_pipe()?;
+
+        let mut command = std::process::Command::new(exe);
+        command.arg(HELPER_ARG).stdin(Stdio::piped());
+        // Safety: this only calls dup2(), which is async-signal-safe.  dup2()
+        // clears close-on-exec on the new descriptor, so it's the only end of
+        // the pipe that the helper inherits.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::dup2(response_write, RESPONSE_FD) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let spawn_result = command.spawn();
+        // Safety: we own both descriptors and nothing else uses them.
+        let responses = unsafe {
+            libc::close(response_write);
+            std::fs::File::from_raw_fd(response_read)
+        };
+        let mut child = spawn_result.context("starting shell helper")?;
+        // This can't fail because we asked for a pipe above.
+        let requests = child.stdin.take().unwrap();
+        Ok(ShellHelper {
+            child,
+            requests,
+            responses: std::io::BufReader::new(responses),
+        })
+    }
+
+    /// Runs `command` with `sh -c`, feeding it `input` on stdin, and waits for
+    /// it to finish
+    ///
+    /// Returns the command's exit status (or the negated signal number, if it
+    /// was killed by a signal).
+    pub fn run(
+        &mut self,
+        command: &str,
+        input: &[u8],
+    ) -> Result<i32, anyhow::Error> {
+        if let Some(status) = self.child.try_wait().context("shell helper")? {
+            bail!("shell helper exited unexpectedly ({})", status);
+        }
+
+        let mut request =
+            format!("{} {}\n{}", command.len(), input.len(), command)
+                .into_bytes();
+        request.extend_from_slice(input);
+        self.requests
+            .write_all(&request)
+            .and_then(|_| self.requests.flush())
+            .context("sending command to shell helper")?;
+
+        let mut response = String::new();
+        self.responses
+            .read_line(&mut response)
+            .context("reading response from shell helper")?;
+        response.trim().parse().map_err(|_| {
+            anyhow!("unexpected response from shell helper: {:?}", response)
+        })
+    }
+}
+
+/// Returns a pipe (read end, write end) whose descriptors are close-on-exec
+fn cloexec_pipe() -> Result<(libc::c_int, libc::c_int), anyhow::Error> {
+    let mut fds = [0; 2];
+    // Safety: `fds` has room for the two descriptors that pipe() returns.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("pipe");
+    }
+    for fd in fds {
+        // Safety: `fd` was just returned by pipe().
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("fcntl");
+        }
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Runs the shell helper: reads requests from stdin until it's closed (i.e.,
+/// when swappy exits)
+pub fn helper_main() -> ! {
+    // Ctrl-C at the terminal is delivered to the whole process group, but it's
+    // meant for swappy (or the shell command), not the helper.
+    // Safety: there's nothing unsafe about ignoring SIGINT.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+    }
+
+    // Safety: swappy gave us this descriptor when it started us.
+    let mut responses = unsafe { std::fs::File::from_raw_fd(RESPONSE_FD) };
+    let mut requests = std::io::BufReader::new(std::io::stdin());
+    loop {
+        let status = match read_request(&mut requests) {
+            Ok(None) => std::process::exit(0),
+            Ok(Some((command, input))) => run_shell(&command, &input),
+            Err(error) => {
+                eprintln!("swappy shell helper: {:#}", error);
+                std::process::exit(1);
+            }
+        };
+        if writeln!(responses, "{}", status).is_err() {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads one request, returning `None` at end-of-file
+fn read_request(
+    requests: &mut impl BufRead,
+) -> Result<Option<(String, Vec<u8>)>, anyhow::Error> {
+    let mut header = String::new();
+    if requests.read_line(&mut header).context("reading request")? == 0 {
+        return Ok(None);
+    }
+    let (command_len, input_len) = header
+        .trim()
+        .split_once(' ')
+        .and_then(|(c, i)| Some((c.parse().ok()?, i.parse().ok()?)))
+        .ok_or_else(|| anyhow!("bad request header: {:?}", header))?;
+
+    let mut command = vec![0; command_len];
+    requests.read_exact(&mut command).context("reading command")?;
+    let command =
+        String::from_utf8(command).context("command is not valid UTF-8")?;
+    let mut input = vec![0; input_len];
+    requests.read_exact(&mut input).context("reading input")?;
+    Ok(Some((command, input)))
+}
+
+/// Runs `command` with `sh -c`, returning its status as described in the
+/// module documentation
+fn run_shell(command: &str, input: &[u8]) -> i32 {
+    let mut shell = std::process::Command::new("sh");
+    shell.arg("-c").arg(command).stdin(Stdio::piped());
+    // Safety: this only calls signal(), which is async-signal-safe.
+    unsafe {
+        shell.pre_exec(|| {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+    let mut child = match shell.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            eprintln!("sh: {}", error);
+            return 127;
+        }
+    };
+
+    // The command may exit without reading all of its input (e.g., "head"),
+    // so ignore errors writing to it.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input);
+    }
+    match child.wait() {
+        Ok(status) => {
+            status.code().or_else(|| status.signal().map(|s| -s)).unwrap_or(-1)
+        }
+        Err(error) => {
+            eprintln!("sh: {}", error);
+            -1
+        }
+    }
+}
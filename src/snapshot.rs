@@ -0,0 +1,247 @@
+//! Named snapshots of the system's memory state
+//!
+//! A snapshot captures swap accounting, physical memory stats, the output of
+//! `::memstat` (when it's available), and the list of mappings created by
+//! swappy.  Snapshots are saved as JSON files (one per snapshot) so that they
+//! survive restarts and can be compared with each other later.
+
+use crate::bytesize_display::ByteSizeDeltaDisplayGiB;
+use crate::bytesize_display::ByteSizeDisplayGiB;
+use crate::kstat::PhysicalMemoryStats;
+use crate::memstat::MemstatRow;
+use crate::output::format_time_of_day;
+use crate::swap::AnonInfo;
+use crate::swappy::MappingInfo;
+use anyhow::bail;
+use anyhow::Context;
+use bytesize::ByteSize;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Directory where snapshots are saved, unless overridden with the
+/// `SWAPPY_SNAPSHOT_DIR` environment variable
+const DEFAULT_SNAPSHOT_DIR: &str = "swappy-snapshots";
+
+/// The system's memory state at one point in time
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub name: String,
+    /// when the snapshot was taken, in seconds since the Unix epoch
+    pub time: f64,
+    pub swapinfo: AnonInfo,
+    pub physmem: PhysicalMemoryStats,
+    /// parsed `::memstat` output, if it could be collected
+    pub memstat: Option<Vec<MemstatRow>>,
+    pub mappings: Vec<MappingInfo>,
+}
+
+impl Snapshot {
+    /// Returns the directory where snapshots are saved
+    pub fn dir() -> PathBuf {
+        std::env::var_os("SWAPPY_SNAPSHOT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SNAPSHOT_DIR))
+    }
+
+    /// Returns when the snapshot was taken
+    pub fn system_time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs_f64(self.time.max(0.0))
+    }
+
+    /// Writes this snapshot to the snapshot directory, replacing any existing
+    /// snapshot with the same name
+    pub fn save(&self) -> Result<PathBuf, anyhow::Error> {
+        let path = snapshot_path(&self.name)?;
+        let dir = Snapshot::dir();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("creating {:?}", dir))?;
+        let contents = serde_json::to_string_pretty(self)
+            .context("serializing snapshot")?;
+        std::fs::write(&path, contents + "\n")
+            .with_context(|| format!("writing {:?}", path))?;
+        Ok(path)
+    }
+
+    /// Reads the snapshot called `name` from the snapshot directory
+    pub fn load(name: &str) -> Result<Snapshot, anyhow::Error> {
+        let path = snapshot_path(name)?;
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading snapshot {:?}", name))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("parsing {:?}", path))
+    }
+
+    /// Reads all snapshots in the snapshot directory, sorted by time
+    pub fn list() -> Result<Vec<Snapshot>, anyhow::Error> {
+        let dir = Snapshot::dir();
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Vec::new());
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("reading {:?}", dir))
+            }
+        };
+
+        let mut rv = Vec::new();
+        for entry in entries {
+            let entry = entry.with_context(|| format!("reading {:?}", dir))?;
+            let path = entry.path();
+            if path.extension().map(|e| e != "json").unwrap_or(true) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                rv.push(Snapshot::load(name)?);
+            }
+        }
+        rv.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(rv)
+    }
+
+    /// Returns an object for displaying the differences between `self` and
+    /// `other`
+    pub fn diff<'a>(&'a self, other: &'a Snapshot) -> SnapshotDiff<'a> {
+        SnapshotDiff { a: self, b: other }
+    }
+
+    /// Returns the named byte values in this snapshot that are compared by
+    /// [`Snapshot::diff()`]
+    fn values(&self) -> Vec<(String, ByteSize)> {
+        let swapinfo = &self.swapinfo;
+        let physmem = &self.physmem;
+        let mut rv = vec![
+            (String::from("swap total"), swapinfo.total()),
+            (String::from("swap available"), swapinfo.available()),
+            (String::from("swap reserved"), swapinfo.reserved()),
+            (String::from("swap allocated"), swapinfo.allocated()),
+            (String::from("physmem"), physmem.physmem),
+            (String::from("freemem"), physmem.freemem),
+            (String::from("availrmem"), physmem.availrmem),
+            (String::from("lotsfree"), physmem.lotsfree),
+            (String::from("desfree"), physmem.desfree),
+            (String::from("minfree"), physmem.minfree),
+        ];
+        if let Some(memstat) = &self.memstat {
+            rv.extend(
+                memstat.iter().map(|row| {
+                    (format!("memstat {}", row.category), row.bytes())
+                }),
+            );
+        }
+        rv
+    }
+}
+
+/// Returns the path of the file for the snapshot called `name`
+fn snapshot_path(name: &str) -> Result<PathBuf, anyhow::Error> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        bail!(
+            "invalid snapshot name {:?} (use letters, digits, '-', '_', and \
+            '.')",
+            name
+        );
+    }
+    Ok(Snapshot::dir().join(Path::new(name).with_extension("json")))
+}
+
+/// Describes the differences between two snapshots
+pub struct SnapshotDiff<'a> {
+    a: &'a Snapshot,
+    b: &'a Snapshot,
+}
+
+impl<'a> std::fmt::Display for SnapshotDiff<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (a, b) = (self.a, self.b);
+        f.write_fmt(format_args!(
+            "comparing {:?} ({}) with {:?} ({})\n",
+            a.name,
+            format_time_of_day(a.system_time()),
+            b.name,
+            format_time_of_day(b.system_time()),
+        ))?;
+        f.write_fmt(format_args!(
+            "{:32} {:>12} {:>12} {:>12}\n",
+            "VALUE", "A (GiB)", "B (GiB)", "DELTA"
+        ))?;
+
+        // Walk the values in `a`'s order, then any that only appear in `b`
+        // (which can happen for memstat categories).
+        let a_values = a.values();
+        let b_values = b.values();
+        let mut names: Vec<&String> = a_values.iter().map(|(n, _)| n).collect();
+        for (name, _) in &b_values {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let lookup = |values: &[(String, ByteSize)], name: &str| {
+            values.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+        };
+        for name in names {
+            let a_value = lookup(&a_values, name);
+            let b_value = lookup(&b_values, name);
+            let show = |value: Option<ByteSize>| match value {
+                Some(value) => ByteSizeDisplayGiB(value).to_string(),
+                None => String::from("-"),
+            };
+            let delta = (b_value.unwrap_or(ByteSize::b(0)).as_u64() as i64)
+                - (a_value.unwrap_or(ByteSize::b(0)).as_u64() as i64);
+            f.write_fmt(format_args!(
+                "{:32} {:>12} {:>12} {:>12}\n",
+                name,
+                show(a_value),
+                show(b_value),
+                ByteSizeDeltaDisplayGiB(delta).to_string(),
+            ))?;
+        }
+
+        f.write_str("\nMAPPINGS\n")?;
+        let mut nchanges = 0;
+        for m in &a.mappings {
+            match b.mappings.iter().find(|bm| bm.addr == m.addr) {
+                None => {
+                    f.write_fmt(format_args!(
+                        "removed  0x{:x} ({} GiB)\n",
+                        m.addr,
+                        ByteSizeDisplayGiB(m.size)
+                    ))?;
+                    nchanges += 1;
+                }
+                Some(bm) if bm != m => {
+                    f.write_fmt(format_args!(
+                        "changed  0x{:x} (touched: {} -> {} GiB)\n",
+                        m.addr,
+                        ByteSizeDisplayGiB(m.touched),
+                        ByteSizeDisplayGiB(bm.touched)
+                    ))?;
+                    nchanges += 1;
+                }
+                Some(_) => (),
+            }
+        }
+        for bm in &b.mappings {
+            if !a.mappings.iter().any(|m| m.addr == bm.addr) {
+                f.write_fmt(format_args!(
+                    "added    0x{:x} ({} GiB, {})\n",
+                    bm.addr,
+                    ByteSizeDisplayGiB(bm.size),
+                    if bm.reserved { "reserved" } else { "noreserve" }
+                ))?;
+                nchanges += 1;
+            }
+        }
+        if nchanges == 0 {
+            f.write_str("no changes\n")?;
+        }
+        Ok(())
+    }
+}
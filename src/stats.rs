@@ -0,0 +1,249 @@
+//! Names for the individual stats collected by the monitor
+//!
+//! These names are used to pick monitor columns and are written as field
+//! names by the file-based monitor outputs.
+
+use crate::monitor::MonitorSample;
+use crate::pressure::MemoryPressure;
+use anyhow::anyhow;
+
+/// Identifies one value in a [`MonitorSample`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stat {
+    Physmem,
+    Freemem,
+    Availrmem,
+    Lotsfree,
+    Desfree,
+    Minfree,
+    SwapAllocated,
+    SwapReserved,
+    SwapAvailable,
+    SwapTotal,
+    PagesScanned,
+    PagesPagedOut,
+    AnonPagesPagedOut,
+    PagesFreed,
+    KmemReaps,
+    ArcSize,
+    ArcTarget,
+    ArcShrinks,
+    ZoneAnonAllocFailures,
+    ZoneSwapReserveFailures,
+    Pressure,
+}
+
+/// Describes what kind of value a [`Stat`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatKind {
+    /// an amount of memory, in bytes
+    Bytes,
+    /// a cumulative count of events (or pages), which is mostly interesting
+    /// in terms of how much it changes from one sample to the next
+    Counter,
+    /// a [`PressureLevel`](crate::pressure::PressureLevel), represented as a
+    /// number from 0 (plenty) to 3 (critical)
+    Pressure,
+}
+
+impl Stat {
+    pub const ALL: &'static [Stat] = &[
+        Stat::Physmem,
+        Stat::Freemem,
+        Stat::Availrmem,
+        Stat::Lotsfree,
+        Stat::Desfree,
+        Stat::Minfree,
+        Stat::SwapAllocated,
+        Stat::SwapReserved,
+        Stat::SwapAvailable,
+        Stat::SwapTotal,
+        Stat::PagesScanned,
+        Stat::PagesPagedOut,
+        Stat::AnonPagesPagedOut,
+        Stat::PagesFreed,
+        Stat::KmemReaps,
+        Stat::ArcSize,
+        Stat::ArcTarget,
+        Stat::ArcShrinks,
+        Stat::ZoneAnonAllocFailures,
+        Stat::ZoneSwapReserveFailures,
+        Stat::Pressure,
+    ];
+
+    /// Returns the name used to select this stat
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stat::Physmem => "physmem",
+            Stat::Freemem => "freemem",
+            Stat::Availrmem => "availrmem",
+            Stat::Lotsfree => "lotsfree",
+            Stat::Desfree => "desfree",
+            Stat::Minfree => "minfree",
+            Stat::SwapAllocated => "swap-allocated",
+            Stat::SwapReserved => "swap-reserved",
+            Stat::SwapAvailable => "swap-available",
+            Stat::SwapTotal => "swap-total",
+            Stat::PagesScanned => "pages-scanned",
+            Stat::PagesPagedOut => "pages-paged-out",
+            Stat::AnonPagesPagedOut => "anon-pages-paged-out",
+            Stat::PagesFreed => "pages-freed",
+            Stat::KmemReaps => "kmem-reaps",
+            Stat::ArcSize => "arc-size",
+            Stat::ArcTarget => "arc-target",
+            Stat::ArcShrinks => "arc-shrinks",
+            Stat::ZoneAnonAllocFailures => "zone-anon-alloc-failures",
+            Stat::ZoneSwapReserveFailures => "zone-swap-reserve-failures",
+            Stat::Pressure => "pressure",
+        }
+    }
+
+    /// Returns the column header used when printing this stat in a table
+    pub fn header(&self) -> &'static str {
+        match self {
+            Stat::Physmem => "PHYSMEM",
+            Stat::Freemem => "FREE",
+            Stat::Availrmem => "AVAILRMEM",
+            Stat::Lotsfree => "LOTSFREE",
+            Stat::Desfree => "DESFREE",
+            Stat::Minfree => "MINFREE",
+            Stat::SwapAllocated => "SWAP_ALLOC",
+            Stat::SwapReserved => "SWAP_RESV",
+            Stat::SwapAvailable => "SWAP_AVAIL",
+            Stat::SwapTotal => "SWAP_TOTAL",
+            Stat::PagesScanned => "SCAN",
+            Stat::PagesPagedOut => "PGOUT",
+            Stat::AnonPagesPagedOut => "ANONPGOUT",
+            Stat::PagesFreed => "PGFREE",
+            Stat::KmemReaps => "KMEM_REAPS",
+            Stat::ArcSize => "ARC_SIZE",
+            Stat::ArcTarget => "ARC_TARGET",
+            Stat::ArcShrinks => "ARC_SHRINKS",
+            Stat::ZoneAnonAllocFailures => "ZONE_ANON_FAIL",
+            Stat::ZoneSwapReserveFailures => "ZONE_RESV_FAIL",
+            Stat::Pressure => "PRESSURE",
+        }
+    }
+
+    /// Returns what kind of value this stat is
+    pub fn kind(&self) -> StatKind {
+        match self {
+            Stat::Physmem
+            | Stat::Freemem
+            | Stat::Availrmem
+            | Stat::Lotsfree
+            | Stat::Desfree
+            | Stat::Minfree
+            | Stat::SwapAllocated
+            | Stat::SwapReserved
+            | Stat::SwapAvailable
+            | Stat::SwapTotal
+            | Stat::ArcSize
+            | Stat::ArcTarget => StatKind::Bytes,
+            Stat::PagesScanned
+            | Stat::PagesPagedOut
+            | Stat::AnonPagesPagedOut
+            | Stat::PagesFreed
+            | Stat::KmemReaps
+            | Stat::ArcShrinks
+            | Stat::ZoneAnonAllocFailures
+            | Stat::ZoneSwapReserveFailures => StatKind::Counter,
+            Stat::Pressure => StatKind::Pressure,
+        }
+    }
+
+    /// Returns the value of this stat in `sample`
+    ///
+    /// This is in bytes for [`StatKind::Bytes`] stats, a plain count for
+    /// [`StatKind::Counter`] stats, and a level number for
+    /// [`StatKind::Pressure`].  It's `None` if the stat's source couldn't be
+    /// read for this sample (e.g., ARC stats when ZFS is not loaded).
+    pub fn value(&self, sample: &MonitorSample) -> Option<u64> {
+        let physmem = &sample.physmem;
+        let swapinfo = &sample.swapinfo;
+        let vm = sample.vm.as_ref();
+        let arc = sample.arc.as_ref();
+        let failures = sample.zone_swap_failures.as_ref();
+        match self {
+            Stat::Physmem => Some(physmem.physmem.as_u64()),
+            Stat::Freemem => Some(physmem.freemem.as_u64()),
+            Stat::Availrmem => Some(physmem.availrmem.as_u64()),
+            Stat::Lotsfree => Some(physmem.lotsfree.as_u64()),
+            Stat::Desfree => Some(physmem.desfree.as_u64()),
+            Stat::Minfree => Some(physmem.minfree.as_u64()),
+            Stat::SwapAllocated => Some(swapinfo.allocated().as_u64()),
+            Stat::SwapReserved => Some(swapinfo.reserved().as_u64()),
+            Stat::SwapAvailable => Some(swapinfo.available().as_u64()),
+            Stat::SwapTotal => Some(swapinfo.total().as_u64()),
+            Stat::PagesScanned => vm.map(|vm| vm.pages_scanned),
+            Stat::PagesPagedOut => vm.map(|vm| vm.pages_paged_out),
+            Stat::AnonPagesPagedOut => vm.map(|vm| vm.anon_pages_paged_out),
+            Stat::PagesFreed => vm.map(|vm| vm.pages_freed),
+            Stat::KmemReaps => sample.kmem_reaps,
+            Stat::ArcSize => arc.map(|a| a.size.as_u64()),
+            Stat::ArcTarget => arc.map(|a| a.target.as_u64()),
+            Stat::ArcShrinks => arc.map(|_| sample.arc_shrinks),
+            Stat::ZoneAnonAllocFailures => {
+                failures.map(|f| f.anon_alloc_failures)
+            }
+            Stat::ZoneSwapReserveFailures => {
+                failures.map(|f| f.swap_reserve_failures)
+            }
+            Stat::Pressure => {
+                Some(MemoryPressure::from_physmem(&sample.physmem).level as u64)
+            }
+        }
+    }
+
+    /// Returns how much this stat changed between `prev` and `sample`, if
+    /// it's available in both
+    pub fn delta(
+        &self,
+        sample: &MonitorSample,
+        prev: &MonitorSample,
+    ) -> Option<i64> {
+        Some((self.value(sample)? as i64) - (self.value(prev)? as i64))
+    }
+
+    /// Returns how fast this stat changed between `prev` and `sample`, per
+    /// second, if it's available in both
+    ///
+    /// This uses the actual time between the two samples rather than the
+    /// configured interval, since samples are not taken exactly on schedule.
+    pub fn rate(
+        &self,
+        sample: &MonitorSample,
+        prev: &MonitorSample,
+    ) -> Option<f64> {
+        let delta = self.delta(sample, prev)?;
+        let elapsed = sample
+            .instant
+            .saturating_duration_since(prev.instant)
+            .as_secs_f64();
+        if elapsed == 0.0 {
+            return Some(0.0);
+        }
+        Some((delta as f64) / elapsed)
+    }
+}
+
+impl std::str::FromStr for Stat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Stat::ALL.iter().find(|c| c.name() == s).copied().ok_or_else(|| {
+            anyhow!("unknown stat {:?} (expected one of: {})", s, stat_names())
+        })
+    }
+}
+
+impl std::fmt::Display for Stat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returns a comma-separated list of all stat names
+pub fn stat_names() -> String {
+    Stat::ALL.iter().map(|c| c.name()).collect::<Vec<_>>().join(", ")
+}
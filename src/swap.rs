@@ -5,6 +5,8 @@ use crate::bytesize_display::ByteSizeDisplayKiB;
 use crate::PAGE_SIZE;
 use anyhow::Context;
 use bytesize::ByteSize;
+use serde::Deserialize;
+use serde::Serialize;
 
 // See sys/swap.h
 const SC_AINFO: libc::c_int = 5;
@@ -14,9 +16,12 @@ extern "C" {
 }
 
 /// Describes illumos swap-related accounting statistics
+///
+/// This is serialized as an [`AnonInfoSummary`].
 // See sys/swap.h
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "AnonInfoSummary", from = "AnonInfoSummary")]
 pub struct AnonInfo {
     ani_max: usize,
     ani_free: usize,
@@ -50,6 +55,43 @@ impl AnonInfo {
     }
 }
 
+/// Serialized form of [`AnonInfo`], with each value in bytes
+#[derive(Serialize, Deserialize)]
+pub struct AnonInfoSummary {
+    /// total swap space (available + used)
+    pub total: u64,
+    /// swap space available for new reservations
+    pub available: u64,
+    /// swap space that has been reserved but not allocated
+    pub reserved: u64,
+    /// swap space for which physical pages have been allocated
+    pub allocated: u64,
+}
+
+impl From<AnonInfo> for AnonInfoSummary {
+    fn from(info: AnonInfo) -> Self {
+        AnonInfoSummary {
+            total: info.total().as_u64(),
+            available: info.available().as_u64(),
+            reserved: info.reserved().as_u64(),
+            allocated: info.allocated().as_u64(),
+        }
+    }
+}
+
+impl From<AnonInfoSummary> for AnonInfo {
+    fn from(summary: AnonInfoSummary) -> Self {
+        // This inverts the calculations in the accessors above.
+        let pages = |bytes: u64| (bytes as usize) / PAGE_SIZE;
+        let ani_max = pages(summary.total);
+        AnonInfo {
+            ani_max,
+            ani_free: ani_max.saturating_sub(pages(summary.allocated)),
+            ani_resv: ani_max.saturating_sub(pages(summary.available)),
+        }
+    }
+}
+
 impl AnonInfo {
     /// Fetch the latest swap accounting stats
     pub fn fetch() -> Result<AnonInfo, anyhow::Error> {
@@ -1,28 +1,98 @@
 //! [`Swappy`] encapsulates the work kicked off by the REPL
 
+use crate::alert::Alert;
+use crate::alert::AlertAction;
+use crate::alert::Condition;
+use crate::interrupt;
+use crate::jobs::JobOutcome;
+use crate::jobs::JobProgress;
+use crate::jobs::JobStatus;
+use crate::jobs::Jobs;
 use crate::kstat::kstat_read_physmem;
-use crate::kstat::PhysicalMemoryStats;
+use crate::kstat::kstat_read_zone_swap_failures;
+use crate::kstat::ZoneSwapFailureStats;
+use crate::memstat::parse_memstat;
 use crate::monitor::Monitor;
+use crate::monitor::MonitorConfig;
+use crate::monitor::MonitorSample;
+use crate::monitor_output::MonitorOutput;
+use crate::output::OutputFormat;
+use crate::pressure::MemoryPressure;
+use crate::residency::Residency;
+use crate::schedule::ScheduledStatus;
+use crate::schedule::Scheduler;
+use crate::shell::ShellHelper;
+use crate::snapshot::Snapshot;
 use crate::swap::AnonInfo;
+use crate::touch::touch_pages;
+use crate::touch::ActiveTouches;
+use crate::touch::TouchOptions;
+use crate::touch::TouchReport;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use bytesize::ByteSize;
+use serde::Deserialize;
+use serde::Serialize;
 use std::os::unix::process::ExitStatusExt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How much of a mapping [`do_swap_rm()`] unmaps at a time
+const RM_CHUNK_SIZE: usize = 256 * 1024 * 1024;
 
 /// Encapsulates the work kicked off by the REPL
 ///
 /// This struct stores the global state of the program and provides interfaces
 /// for manipulating and inspecting it.  Currently, that's mostly the list of
-/// anonymous mappings that have been created.
+/// anonymous mappings that have been created and any background jobs operating
+/// on them.
+///
+/// Cloning a `Swappy` returns another handle to the same mappings, monitor,
+/// and jobs (e.g., for use by a script or from another thread).  Settings like
+/// the output format are copied rather than shared.
+#[derive(Clone)]
 pub struct Swappy {
-    mappings: Vec<Mapping>,
-    monitor: Monitor,
+    mappings: Arc<Mappings>,
+    monitor: Arc<Monitor>,
+    jobs: Arc<Mutex<Jobs>>,
+    touches: Arc<ActiveTouches>,
+    scheduler: Arc<Scheduler>,
+    /// helper used to run shell commands (see [`crate::shell`]), if started
+    shell: Arc<Mutex<Option<ShellHelper>>>,
+    output_format: OutputFormat,
+    /// zone cap swap failure counts as of the last call to
+    /// [`Swappy::zone_swap_failures_since_last_check()`]
+    zone_swap_failures: Option<ZoneSwapFailureStats>,
 }
 
 impl Swappy {
+    // This starts the monitor and scheduler threads, which is more than
+    // Default should do.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Swappy {
-        Swappy { mappings: Vec::new(), monitor: Monitor::new() }
+        let touches = Arc::new(ActiveTouches::default());
+        Swappy {
+            mappings: Arc::new(Mappings::default()),
+            monitor: Arc::new(Monitor::new(Arc::clone(&touches))),
+            jobs: Arc::new(Mutex::new(Jobs::new())),
+            touches,
+            scheduler: Arc::new(Scheduler::new()),
+            shell: Arc::new(Mutex::new(None)),
+            output_format: OutputFormat::default(),
+            zone_swap_failures: fetch_zone_swap_failures().ok(),
+        }
+    }
+
+    /// Returns the format that commands should use for their output
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Set the format that commands should use for their output
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
     }
 
     /// Returns summary swap accounting stats (like `swap -s`)
@@ -30,9 +100,9 @@ impl Swappy {
         AnonInfo::fetch()
     }
 
-    /// Iterate mappings created by the user
-    pub fn mappings(&self) -> impl std::iter::Iterator<Item = &Mapping> {
-        self.mappings.iter()
+    /// Returns a copy of the list of mappings created by the user
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.list()
     }
 
     /// Create a swap mapping (using mmap), returning the address
@@ -67,60 +137,343 @@ impl Swappy {
                 .context("mmap anon memory");
         }
 
-        self.mappings.push(Mapping { addr, size, reserved, allocated: false });
+        self.mappings.push(Mapping {
+            addr,
+            size,
+            reserved,
+            touched: 0,
+            busy: false,
+        });
         Ok(addr as usize)
     }
 
     /// Remove a swap mapping identified by address
-    pub fn swap_rm(&mut self, addr: usize) -> Result<(), anyhow::Error> {
-        let mapping = self
-            .mappings
-            .iter_mut()
-            .find(|m| m.addr as usize == addr)
-            .ok_or_else(|| anyhow!("no mapping with address 0x{:x}", addr))?;
+    ///
+    /// If interrupted with SIGINT, this stops early and returns
+    /// [`JobOutcome::Cancelled`].  The part of the mapping that hasn't been
+    /// removed yet remains, at the same address (see [`do_swap_rm()`]).
+    pub fn swap_rm(
+        &mut self,
+        addr: usize,
+    ) -> Result<JobOutcome, anyhow::Error> {
+        let mapping = self.mappings.claim(addr)?;
+        let progress = JobProgress::new(mapping.size as u64);
+        let interrupt = interrupt::interruptible();
+        interrupt.cancel_on_interrupt(&progress, || {
+            do_swap_rm(&self.mappings, &self.monitor, &mapping, &progress)
+        })
+    }
 
-        let (addr, size, allocated) =
-            (mapping.addr, mapping.size, mapping.allocated);
-        if allocated {
-            self.monitor.enable();
-        }
-        let rv = unsafe { libc::munmap(addr, size) };
-        let error = std::io::Error::last_os_error();
-        if allocated {
-            self.monitor.disable();
-        }
+    /// Remove a swap mapping identified by address in a background job,
+    /// returning the job's id
+    pub fn swap_rm_background(
+        &mut self,
+        addr: usize,
+    ) -> Result<usize, anyhow::Error> {
+        let mapping = self.mappings.claim(addr)?;
+        let mappings = Arc::clone(&self.mappings);
+        let monitor = Arc::clone(&self.monitor);
+        let description = format!("remove 0x{:x}", addr);
+        Ok(self.jobs.lock().unwrap().spawn(
+            description,
+            mapping.size as u64,
+            move |progress| do_swap_rm(&mappings, &monitor, &mapping, progress),
+        ))
+    }
 
-        if rv != 0 {
-            return Err(error).context("munmap");
-        }
+    /// Touch all pages in a swap mapping (or the first `options.percent` of
+    /// them), in order to allocate them
+    ///
+    /// If interrupted with SIGINT, this stops before touching the next page.
+    /// The report's `outcome` is then [`JobOutcome::Cancelled`] and it
+    /// describes the pages touched so far.
+    ///
+    /// This doesn't save the latency samples to `options.latency_file`, so
+    /// that failing to save them doesn't lose the report.  Use
+    /// [`TouchReport::save_latency_samples()`] for that.
+    pub fn swap_touch(
+        &mut self,
+        addr: usize,
+        options: &TouchOptions,
+    ) -> Result<TouchReport, anyhow::Error> {
+        options.validate()?;
+        let mapping = self.mappings.claim_for_touch(addr, options)?;
+        let size = options.touch_size(mapping.size);
+        let progress = Arc::new(JobProgress::new(size as u64));
+        let registration = self.touches.register(&progress);
+        let interrupt = interrupt::interruptible();
+        self.monitor.enable();
+        let mut report = interrupt.cancel_on_interrupt(&progress, || {
+            touch_pages(mapping.addr as usize, size, options, &progress)
+        });
+        self.monitor.disable();
+        drop(registration);
+        self.mappings.release(addr);
+        report.already_touched = mapping.touched > 0;
+        Ok(report)
+    }
 
-        self.mappings.retain(|m| m.addr != addr);
-        Ok(())
+    /// Touch all pages in a swap mapping in a background job, returning the
+    /// job's id
+    ///
+    /// See [`Swappy::swap_touch()`].
+    pub fn swap_touch_background(
+        &mut self,
+        addr: usize,
+        options: &TouchOptions,
+    ) -> Result<usize, anyhow::Error> {
+        options.validate()?;
+        let mapping = self.mappings.claim_for_touch(addr, options)?;
+        let mappings = Arc::clone(&self.mappings);
+        let monitor = Arc::clone(&self.monitor);
+        let touches = Arc::clone(&self.touches);
+        let options = options.clone();
+        let size = options.touch_size(mapping.size);
+        let description = format!("touch 0x{:x}", addr);
+        Ok(self.jobs.lock().unwrap().spawn(
+            description,
+            size as u64,
+            move |progress| {
+                let registration = touches.register(progress);
+                monitor.enable();
+                let report = touch_pages(addr, size, &options, progress);
+                monitor.disable();
+                drop(registration);
+                mappings.release(addr);
+                println!("{}", report);
+                if let Some(path) = &options.latency_file {
+                    report.save_latency_samples(path)?;
+                }
+                Ok(report.outcome)
+            },
+        ))
     }
 
-    /// Touch all pages in a swap mapping (in order to allocate them)
-    pub fn swap_touch(&mut self, addr: usize) -> Result<bool, anyhow::Error> {
-        let mut mapping = self
-            .mappings
-            .iter_mut()
-            .find(|m| m.addr as usize == addr)
-            .ok_or_else(|| anyhow!("no mapping with address 0x{:x}", addr))?;
+    /// Reports how much of each mapping is resident in physical memory
+    ///
+    /// If `addr` is provided, only that mapping is reported.  Mappings that a
+    /// background job is operating on are reported without a residency, since
+    /// a removal may have already unmapped part of them.
+    pub fn swap_residency(
+        &self,
+        addr: Option<usize>,
+    ) -> Result<Vec<(Mapping, Option<Residency>)>, anyhow::Error> {
+        let mappings: Vec<Mapping> = match addr {
+            None => self.mappings(),
+            Some(addr) => {
+                let mapping = self
+                    .mappings()
+                    .into_iter()
+                    .find(|m| m.addr as usize == addr)
+                    .ok_or_else(|| {
+                        anyhow!("no mapping with address 0x{:x}", addr)
+                    })?;
+                vec![mapping]
+            }
+        };
+
+        mappings
+            .into_iter()
+            .map(|m| {
+                if m.busy {
+                    return Ok((m, None));
+                }
+                let residency = Residency::fetch(m.addr as usize, m.size)
+                    .with_context(|| format!("mapping {:p}", m.addr))?;
+                Ok((m, Some(residency)))
+            })
+            .collect()
+    }
+
+    /// Returns where the monitor sends the stats it collects
+    pub fn monitor_outputs(&self) -> Vec<MonitorOutput> {
+        self.monitor.outputs()
+    }
+
+    /// Configure where the monitor sends the stats it collects
+    pub fn monitor_set_outputs(
+        &mut self,
+        outputs: Vec<MonitorOutput>,
+    ) -> Result<(), anyhow::Error> {
+        self.monitor.set_outputs(outputs)
+    }
 
-        let rv = !mapping.allocated;
-        mapping.allocated = true;
+    /// Returns the alerts that the monitor checks on each sample
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.monitor.alerts()
+    }
+
+    /// Add an alert for the monitor to check on each sample, returning its id
+    pub fn alert_add(
+        &mut self,
+        condition: Condition,
+        action: AlertAction,
+    ) -> Result<usize, anyhow::Error> {
+        self.monitor.add_alert(condition, action)
+    }
+
+    /// Remove the alert with id `id`
+    pub fn alert_rm(&mut self, id: usize) -> Result<(), anyhow::Error> {
+        self.monitor.remove_alert(id)
+    }
+
+    /// Returns the samples that the monitor collected within the last `since`
+    /// (or all retained samples, if `since` is `None`), oldest first
+    pub fn history(&self, since: Option<Duration>) -> Vec<Arc<MonitorSample>> {
+        self.monitor.history(since)
+    }
+
+    /// Returns the monitor's configuration
+    pub fn monitor_config(&self) -> MonitorConfig {
+        self.monitor.config()
+    }
+
+    /// Replace the monitor's configuration
+    pub fn monitor_set_config(
+        &mut self,
+        config: MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        self.monitor.set_config(config)
+    }
+
+    /// Run the monitor until it has collected `count` samples (or forever, if
+    /// `count` is `None`) or until interrupted with SIGINT
+    ///
+    /// If `interval` is specified, it overrides the configured interval while
+    /// this runs.  Returns `true` if interrupted.
+    pub fn monitor_watch(
+        &mut self,
+        count: Option<u64>,
+        interval: Option<Duration>,
+    ) -> Result<bool, anyhow::Error> {
+        let interrupt = interrupt::interruptible();
+        let saved_config = match interval {
+            None => None,
+            Some(interval) => {
+                let saved_config = self.monitor.config();
+                let config = MonitorConfig { interval, ..saved_config.clone() };
+                self.monitor.set_config(config)?;
+                Some(saved_config)
+            }
+        };
 
-        let start_addr = mapping.addr as usize;
-        let end_addr = mapping.addr as usize + mapping.size;
         self.monitor.enable();
+        let interrupted = self.monitor.wait_samples(count, None, &interrupt);
+        self.monitor.disable();
 
-        for page_addr in (start_addr..end_addr).step_by(crate::PAGE_SIZE) {
-            let page_ptr: *mut u8 = page_addr as *mut u8;
-            unsafe { std::ptr::write(page_ptr, 1) };
+        if let Some(saved_config) = saved_config {
+            self.monitor.set_config(saved_config)?;
         }
 
+        Ok(interrupted)
+    }
+
+    /// Waits until `condition` holds, checking it each time the monitor takes
+    /// a sample (the monitor is enabled while this runs)
+    ///
+    /// Returns the sample in which the condition held, or `None` if
+    /// interrupted with SIGINT.  Returns an error if `timeout` elapses first.
+    pub fn wait_until(
+        &mut self,
+        condition: &Condition,
+        timeout: Option<Duration>,
+    ) -> Result<Option<MonitorSample>, anyhow::Error> {
+        let interrupt = interrupt::interruptible();
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        self.monitor.enable();
+        let result = loop {
+            let sample = match MonitorSample::fetch() {
+                Ok(sample) => sample,
+                Err(error) => break Err(error),
+            };
+            if condition.eval(&sample) {
+                break Ok(Some(sample));
+            }
+            if let (Some(timeout), Some(deadline)) = (timeout, deadline) {
+                if std::time::Instant::now() >= deadline {
+                    break Err(anyhow!(
+                        "timed out after {:?} waiting for {} ({})",
+                        timeout,
+                        condition,
+                        condition.describe_values(&sample)
+                    ));
+                }
+            }
+            // Wake up at the deadline, even if the next sample isn't due, so
+            // that we don't wait for a whole interval past the timeout.
+            if self.monitor.wait_samples(Some(1), deadline, &interrupt) {
+                break Ok(None);
+            }
+        };
         self.monitor.disable();
+        result
+    }
 
-        Ok(rv)
+    /// Returns the status of background jobs
+    ///
+    /// Jobs that have finished are reported once and then forgotten.
+    pub fn jobs(&mut self) -> Vec<JobStatus> {
+        self.jobs.lock().unwrap().list()
+    }
+
+    /// Ask a background job to stop at its next opportunity
+    ///
+    /// Touch jobs stop before touching the next page.  Removals stop before
+    /// unmapping the next chunk, leaving the rest of the mapping in place.
+    pub fn job_cancel(&mut self, id: usize) -> Result<(), anyhow::Error> {
+        self.jobs.lock().unwrap().cancel(id)
+    }
+
+    /// Schedule `action` to run after `delay` and then (if `every` is
+    /// specified) repeatedly at that interval, returning its id
+    ///
+    /// The action runs on a separate thread, so it needs its own handle to any
+    /// state it uses (see [`Swappy`]'s `Clone` impl).
+    pub fn schedule_add(
+        &mut self,
+        description: String,
+        delay: Duration,
+        every: Option<Duration>,
+        action: impl FnMut() + Send + 'static,
+    ) -> usize {
+        self.scheduler.add(description, delay, every, Box::new(action))
+    }
+
+    /// Returns the actions that are scheduled to run, soonest first
+    pub fn schedule_list(&self) -> Vec<ScheduledStatus> {
+        self.scheduler.list()
+    }
+
+    /// Cancel a scheduled action
+    pub fn schedule_cancel(&mut self, id: usize) -> Result<(), anyhow::Error> {
+        self.scheduler.cancel(id)
+    }
+
+    /// Starts the helper process that [`Swappy::run_shell()`] uses
+    ///
+    /// This should be called at startup, before creating any mappings.  The
+    /// helper runs the current executable with [`crate::shell::HELPER_ARG`],
+    /// so the program must call [`crate::shell::helper_main()`] when it's
+    /// given that argument.
+    pub fn start_shell_helper(&mut self) -> Result<(), anyhow::Error> {
+        *self.shell.lock().unwrap() = Some(ShellHelper::start()?);
+        Ok(())
+    }
+
+    /// Runs `command` with the shell, feeding it `input` on stdin, and waits
+    /// for it to finish, returning its exit status
+    ///
+    /// The command is started by a helper process rather than by forking
+    /// swappy, so it doesn't matter how much swap swappy has reserved.
+    pub fn run_shell(
+        &self,
+        command: &str,
+        input: &[u8],
+    ) -> Result<i32, anyhow::Error> {
+        match &mut *self.shell.lock().unwrap() {
+            Some(shell) => shell.run(command, input),
+            None => Err(anyhow!("the shell helper is not running")),
+        }
     }
 
     /// Run mdb's ::memstat to print a summary of physical memory usage by
@@ -139,7 +492,7 @@ impl Swappy {
             .arg("-ke")
             .arg("::memstat")
             .output()
-            .expect("failed to run: `pfexec mdb -ke ::memstat`");
+            .context("failed to run: `pfexec mdb -ke ::memstat`")?;
         let stdout = String::from_utf8_lossy(&cmd_output.stdout);
         let stderr = String::from_utf8_lossy(&cmd_output.stderr);
         if !cmd_output.status.success() {
@@ -167,16 +520,211 @@ impl Swappy {
         Ok(stdout.to_string())
     }
 
+    /// Classifies how much memory pressure the system is under
+    pub fn pressure(&self) -> Result<MemoryPressure, anyhow::Error> {
+        let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
+        let physmem =
+            kstat_read_physmem(&kstat).context("kstat_read_physmem")?;
+        Ok(MemoryPressure::from_physmem(&physmem))
+    }
+
+    /// Capture the system's current memory state as a snapshot called `name`
+    ///
+    /// This does not save the snapshot (see [`Snapshot::save()`]).
+    pub fn snapshot(&self, name: &str) -> Result<Snapshot, anyhow::Error> {
+        let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
+        let physmem =
+            kstat_read_physmem(&kstat).context("kstat_read_physmem")?;
+        let swapinfo = AnonInfo::fetch()?;
+        let memstat = match Swappy::memstat() {
+            Ok(output) => Some(parse_memstat(&output)),
+            Err(error) => {
+                eprintln!("warning: skipping memstat: {:#}", error);
+                None
+            }
+        };
+        let mappings = self.mappings().iter().map(Mapping::info).collect();
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        Ok(Snapshot {
+            name: name.to_string(),
+            time,
+            swapinfo,
+            physmem,
+            memstat,
+            mappings,
+        })
+    }
+
     /// Fetch various memory-related kstats
-    pub fn kstat_read(&mut self) -> Result<PhysicalMemoryStats, anyhow::Error> {
+    pub fn kstat_read(&mut self) -> Result<MonitorSample, anyhow::Error> {
         // TODO How are you supposed to do this?  I want to hang the `kstat_ctl`
         // off of `self.kstat` but I can't because update() consumes it.
-        let kstat = kstat_rs::Ctl::new().expect("initializing kstat");
-        kstat_read_physmem(&kstat)
+        MonitorSample::fetch()
+    }
+
+    /// Returns how many swap allocations and reservations have failed
+    /// because of zone caps since the last time this was called (or since
+    /// startup)
+    pub fn zone_swap_failures_since_last_check(
+        &mut self,
+    ) -> Result<ZoneSwapFailureStats, anyhow::Error> {
+        let current = fetch_zone_swap_failures()?;
+        let increase = match &self.zone_swap_failures {
+            Some(last) => current.since(last),
+            None => ZoneSwapFailureStats::default(),
+        };
+        self.zone_swap_failures = Some(current);
+        Ok(increase)
+    }
+}
+
+fn fetch_zone_swap_failures() -> Result<ZoneSwapFailureStats, anyhow::Error> {
+    let kstat = kstat_rs::Ctl::new().context("initializing kstat")?;
+    kstat_read_zone_swap_failures(&kstat)
+}
+
+/// Unmap `mapping`, which must already have been claimed with
+/// [`Mappings::claim()`]
+///
+/// The mapping is unmapped in chunks of [`RM_CHUNK_SIZE`], starting from the
+/// end, so that a removal of a large, touched mapping can be cancelled
+/// partway through (using `progress`).  In that case (or if `munmap` fails
+/// partway through), the rest of the mapping remains, at the same address,
+/// with a smaller size.
+fn do_swap_rm(
+    mappings: &Mappings,
+    monitor: &Monitor,
+    mapping: &Mapping,
+    progress: &JobProgress,
+) -> Result<JobOutcome, anyhow::Error> {
+    if mapping.touched > 0 {
+        monitor.enable();
+    }
+    let mut remaining = mapping.size;
+    let result = loop {
+        if remaining == 0 {
+            break Ok(JobOutcome::Completed);
+        }
+        if progress.is_cancelled() {
+            break Ok(JobOutcome::Cancelled);
+        }
+        let chunk_start = (remaining - 1) / RM_CHUNK_SIZE * RM_CHUNK_SIZE;
+        let chunk_addr = (mapping.addr as usize + chunk_start) as *mut _;
+        let chunk_size = remaining - chunk_start;
+        if unsafe { libc::munmap(chunk_addr, chunk_size) } != 0 {
+            break Err(std::io::Error::last_os_error()).context("munmap");
+        }
+        progress.advance(chunk_size as u64);
+        remaining = chunk_start;
+    };
+    if mapping.touched > 0 {
+        monitor.disable();
+    }
+
+    if remaining == 0 {
+        mappings.remove(mapping.addr as usize);
+    } else {
+        mappings.release_truncated(mapping.addr as usize, remaining);
+    }
+    result
+}
+
+/// The list of user-created mappings, shared with background jobs
+///
+/// While a mapping is being touched or removed, it's marked busy so that
+/// nothing else tries to operate on it (in particular, so that nobody unmaps it
+/// while we're touching it).
+#[derive(Default)]
+struct Mappings(Mutex<Vec<Mapping>>);
+
+impl Mappings {
+    fn list(&self) -> Vec<Mapping> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, mapping: Mapping) {
+        self.0.lock().unwrap().push(mapping);
+    }
+
+    /// Marks the mapping at `addr` busy, returning a copy of it
+    ///
+    /// The caller must call [`Mappings::release()`] or [`Mappings::remove()`]
+    /// when finished with it.
+    fn claim(&self, addr: usize) -> Result<Mapping, anyhow::Error> {
+        self.do_claim(addr, None)
+    }
+
+    /// Like [`Mappings::claim()`], but also records that the part of the
+    /// mapping described by `options` has been touched
+    ///
+    /// The returned copy reflects how much of the mapping was touched _before_
+    /// this call.
+    fn claim_for_touch(
+        &self,
+        addr: usize,
+        options: &TouchOptions,
+    ) -> Result<Mapping, anyhow::Error> {
+        self.do_claim(addr, Some(options))
+    }
+
+    fn do_claim(
+        &self,
+        addr: usize,
+        touch: Option<&TouchOptions>,
+    ) -> Result<Mapping, anyhow::Error> {
+        let mut mappings = self.0.lock().unwrap();
+        let mapping = mappings
+            .iter_mut()
+            .find(|m| m.addr as usize == addr)
+            .ok_or_else(|| anyhow!("no mapping with address 0x{:x}", addr))?;
+        if mapping.busy {
+            bail!("mapping 0x{:x} is in use by a background job", addr);
+        }
+
+        let rv = mapping.clone();
+        mapping.busy = true;
+        if let Some(options) = touch {
+            // Touches always start at the beginning of the mapping.
+            let size = options.touch_size(mapping.size);
+            mapping.touched = mapping.touched.max(size);
+        }
+        Ok(rv)
+    }
+
+    /// Releases a mapping previously returned by [`Mappings::claim()`]
+    fn release(&self, addr: usize) {
+        let mut mappings = self.0.lock().unwrap();
+        if let Some(mapping) =
+            mappings.iter_mut().find(|m| m.addr as usize == addr)
+        {
+            mapping.busy = false;
+        }
+    }
+
+    /// Releases a mapping previously returned by [`Mappings::claim()`] after
+    /// all but its first `size` bytes have been unmapped
+    fn release_truncated(&self, addr: usize, size: usize) {
+        let mut mappings = self.0.lock().unwrap();
+        if let Some(mapping) =
+            mappings.iter_mut().find(|m| m.addr as usize == addr)
+        {
+            mapping.size = size;
+            mapping.touched = mapping.touched.min(size);
+            mapping.busy = false;
+        }
+    }
+
+    /// Forgets about a mapping previously returned by [`Mappings::claim()`]
+    fn remove(&self, addr: usize) {
+        self.0.lock().unwrap().retain(|m| m.addr as usize != addr);
     }
 }
 
 /// Describes one user-created swap mapping
+#[derive(Clone)]
 pub struct Mapping {
     /// the address of the mapping
     ///
@@ -190,14 +738,96 @@ pub struct Mapping {
     /// whether the user requested that the mapping reserve swap space
     pub reserved: bool,
 
-    /// whether the pages in this mapping have been touched using
-    /// [`Swappy::swap_touch()`]
-    pub allocated: bool,
+    /// number of bytes at the start of this mapping that have been touched
+    /// using [`Swappy::swap_touch()`]
+    touched: usize,
+
+    /// whether a touch or removal is currently operating on this mapping
+    busy: bool,
 }
 
+// `Mapping` only describes a region of our own address space.  It's fine to
+// operate on that region from any thread.
+unsafe impl Send for Mapping {}
+
 impl Mapping {
     /// Returns the size of the mapping
     pub fn size(&self) -> ByteSize {
         ByteSize::b(u64::try_from(self.size).unwrap())
     }
+
+    /// Returns a serializable description of this mapping
+    pub fn info(&self) -> MappingInfo {
+        MappingInfo {
+            addr: self.addr as usize,
+            size: self.size(),
+            reserved: self.reserved,
+            allocated: self.allocated(),
+            touched: self.touched(),
+        }
+    }
+
+    /// Returns how much of the mapping (from the beginning) has been touched
+    /// using [`Swappy::swap_touch()`]
+    pub fn touched(&self) -> ByteSize {
+        ByteSize::b(u64::try_from(self.touched).unwrap())
+    }
+
+    /// Returns whether all of the pages in this mapping have been touched
+    /// using [`Swappy::swap_touch()`]
+    pub fn allocated(&self) -> bool {
+        self.touched == self.size
+    }
+
+    /// Returns whether a background job is currently operating on this
+    /// mapping
+    pub fn busy(&self) -> bool {
+        self.busy
+    }
+}
+
+impl Serialize for Mapping {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.info().serialize(serializer)
+    }
+}
+
+/// Serializable description of a [`Mapping`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MappingInfo {
+    /// the address of the mapping (serialized as a hex string, since
+    /// addresses often don't fit in a JSON number)
+    #[serde(with = "hex_addr")]
+    pub addr: usize,
+    /// the size of the mapping, in bytes
+    pub size: ByteSize,
+    /// whether the mapping reserved swap space
+    pub reserved: bool,
+    /// whether all of the pages in the mapping have been touched
+    pub allocated: bool,
+    /// how much of the mapping (from the beginning) has been touched
+    #[serde(default)]
+    pub touched: ByteSize,
+}
+
+/// Serializes addresses as hex strings (e.g., "0xfffffc7d40000000")
+mod hex_addr {
+    use serde::Deserialize;
+
+    pub fn serialize<S: serde::Serializer>(
+        addr: &usize,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:x}", addr))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<usize, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_int::parse(&s).map_err(serde::de::Error::custom)
+    }
 }
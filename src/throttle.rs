@@ -0,0 +1,56 @@
+//! Rate-limiting for operations that touch memory
+
+use bytesize::ByteSize;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Paces an operation to a given number of bytes per second
+///
+/// This is a token bucket: tokens (bytes) accumulate at the configured rate up
+/// to a fixed capacity and each call to [`TokenBucket::take()`] consumes some.
+/// When there aren't enough tokens, the caller sleeps until the bucket has
+/// refilled.  The capacity is 1/10th of a second's worth of bytes so that the
+/// operation proceeds in small bursts that look smooth at the monitor's
+/// one-second granularity.
+pub struct TokenBucket {
+    /// rate at which tokens accumulate (bytes per second)
+    rate: f64,
+    /// maximum number of tokens that can accumulate
+    capacity: f64,
+    /// number of tokens currently available
+    tokens: f64,
+    /// when we last added tokens to the bucket
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Returns a token bucket that allows `rate` bytes per second, where each
+    /// call to [`TokenBucket::take()`] will consume at most `max_take` bytes
+    pub fn new(rate: ByteSize, max_take: usize) -> TokenBucket {
+        let rate = rate.as_u64() as f64;
+        let capacity = (rate / 10.0).max(max_take as f64);
+        TokenBucket { rate, capacity, tokens: 0.0, last_refill: Instant::now() }
+    }
+
+    /// Consume `nbytes` tokens, sleeping first if there aren't enough
+    pub fn take(&mut self, nbytes: usize) {
+        let nbytes = nbytes as f64;
+        self.refill();
+        if self.tokens < nbytes {
+            // Rather than sleeping just long enough for this request, wait for
+            // the whole bucket to fill.  That way we sleep about ten times per
+            // second instead of once per page.
+            let wait = (self.capacity - self.tokens) / self.rate;
+            std::thread::sleep(Duration::from_secs_f64(wait));
+            self.refill();
+        }
+        self.tokens -= nbytes;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
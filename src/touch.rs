@@ -0,0 +1,467 @@
+//! Touching the pages of a mapping (in order to allocate them)
+
+use crate::bytesize_display::ByteSizeDisplayGiB;
+use crate::histogram::Histogram;
+use crate::jobs::JobOutcome;
+use crate::jobs::JobProgress;
+use crate::throttle::TokenBucket;
+use crate::PAGE_SIZE;
+use anyhow::bail;
+use anyhow::Context;
+use bytesize::ByteSize;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Describes how to touch a mapping
+#[derive(Clone, Debug)]
+pub struct TouchOptions {
+    /// if specified, touch no more than this many bytes per second (in total,
+    /// across all threads)
+    pub rate: Option<ByteSize>,
+    /// number of threads to split the work across
+    pub nthreads: usize,
+    /// if specified, time every Nth page write and report the distribution of
+    /// latencies
+    pub latency_sample: Option<usize>,
+    /// if specified, save each latency sample to this file
+    pub latency_file: Option<PathBuf>,
+    /// if specified, touch only this percentage of the mapping's pages
+    /// (starting from the beginning of the mapping)
+    pub percent: Option<f64>,
+}
+
+impl Default for TouchOptions {
+    fn default() -> Self {
+        TouchOptions {
+            rate: None,
+            nthreads: 1,
+            latency_sample: None,
+            latency_file: None,
+            percent: None,
+        }
+    }
+}
+
+impl TouchOptions {
+    /// Returns an error if these options don't make sense
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(rate) = self.rate {
+            if rate.as_u64() == 0 {
+                bail!("touch rate must be non-zero");
+            }
+        }
+        if self.nthreads == 0 {
+            bail!("number of threads must be non-zero");
+        }
+        if self.latency_sample == Some(0) {
+            bail!("latency sampling interval must be non-zero");
+        }
+        if self.latency_file.is_some() && self.latency_sample.is_none() {
+            bail!("cannot save latency samples without sampling latency");
+        }
+        if let Some(percent) = self.percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                bail!("percentage to touch must be in (0, 100]");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how many bytes at the start of a mapping of `size` bytes should
+    /// be touched (a whole number of pages, unless that's all of it)
+    pub fn touch_size(&self, size: usize) -> usize {
+        match self.percent {
+            None => size,
+            Some(percent) => {
+                let npages = size.div_ceil(PAGE_SIZE);
+                let ntouch =
+                    ((npages as f64) * percent / 100.0).ceil() as usize;
+                (ntouch * PAGE_SIZE).min(size)
+            }
+        }
+    }
+}
+
+/// Describes the work done by one touch operation
+#[derive(Debug)]
+pub struct TouchReport {
+    /// whether the mapping's pages had already been touched before this
+    pub already_touched: bool,
+    /// whether the touch ran to completion
+    pub outcome: JobOutcome,
+    /// wall-clock time for the whole operation
+    pub elapsed: Duration,
+    /// per-thread breakdown
+    pub threads: Vec<TouchThreadReport>,
+}
+
+/// Describes the work done by one thread of a touch operation
+#[derive(Debug)]
+pub struct TouchThreadReport {
+    /// number of pages touched by this thread
+    pub npages: u64,
+    /// how long this thread spent touching pages
+    pub elapsed: Duration,
+    /// distribution of page write latencies (if requested)
+    pub latency: Option<Histogram>,
+    /// individual latency samples, as (page index within the mapping, latency)
+    /// (if requested)
+    pub latency_samples: Vec<(usize, Duration)>,
+}
+
+impl TouchReport {
+    /// Returns the total number of pages touched by all threads
+    pub fn npages(&self) -> u64 {
+        self.threads.iter().map(|t| t.npages).sum()
+    }
+
+    /// Returns the distribution of page write latencies across all threads
+    /// (if latency was sampled)
+    pub fn latency(&self) -> Option<Histogram> {
+        let mut rv: Option<Histogram> = None;
+        for thread_latency in
+            self.threads.iter().filter_map(|t| t.latency.as_ref())
+        {
+            rv.get_or_insert_with(Histogram::new).merge(thread_latency);
+        }
+        rv
+    }
+
+    /// Write each latency sample to the file at `path` as CSV
+    pub fn save_latency_samples(
+        &self,
+        path: &std::path::Path,
+    ) -> Result<(), anyhow::Error> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("creating {:?}", path))?;
+        let mut file = std::io::BufWriter::new(file);
+        let mut write_all = || -> Result<(), std::io::Error> {
+            writeln!(file, "thread,page,latency_ns")?;
+            for (i, t) in self.threads.iter().enumerate() {
+                for (page, latency) in &t.latency_samples {
+                    writeln!(file, "{},{},{}", i, page, latency.as_nanos())?;
+                }
+            }
+            file.flush()
+        };
+        write_all().with_context(|| format!("writing {:?}", path))
+    }
+}
+
+/// Serializable summary of a [`TouchReport`] (see [`TouchReport::summary()`])
+#[derive(Debug, Serialize)]
+pub struct TouchSummary {
+    pub already_touched: bool,
+    pub outcome: JobOutcome,
+    /// total pages touched by all threads
+    pub npages: u64,
+    /// wall-clock time for the whole operation, in seconds
+    pub elapsed_secs: f64,
+    pub threads: Vec<TouchThreadSummary>,
+    /// page write latency percentiles, if latency was sampled
+    pub latency_ns: Option<LatencySummary>,
+}
+
+/// Serializable summary of a [`TouchThreadReport`]
+#[derive(Debug, Serialize)]
+pub struct TouchThreadSummary {
+    pub npages: u64,
+    pub elapsed_secs: f64,
+}
+
+/// Page write latency percentiles, in nanoseconds
+#[derive(Debug, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: u128,
+    pub p90: u128,
+    pub p99: u128,
+    pub max: u128,
+}
+
+impl TouchReport {
+    /// Returns a serializable summary of this report
+    pub fn summary(&self) -> TouchSummary {
+        TouchSummary {
+            already_touched: self.already_touched,
+            outcome: self.outcome,
+            npages: self.npages(),
+            elapsed_secs: self.elapsed.as_secs_f64(),
+            threads: self
+                .threads
+                .iter()
+                .map(|t| TouchThreadSummary {
+                    npages: t.npages,
+                    elapsed_secs: t.elapsed.as_secs_f64(),
+                })
+                .collect(),
+            latency_ns: self.latency().map(|h| LatencySummary {
+                count: h.count(),
+                p50: h.percentile(50.0).as_nanos(),
+                p90: h.percentile(90.0).as_nanos(),
+                p99: h.percentile(99.0).as_nanos(),
+                max: h.max().as_nanos(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for TouchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TOUCH SUMMARY\n")?;
+        f.write_fmt(format_args!(
+            "{:>6}  {:>10}  {:>11}  {:>10}  {:>7}\n",
+            "THREAD", "PAGES", "ELAPSED (s)", "PAGES/SEC", "GiB/SEC"
+        ))?;
+        for (i, t) in self.threads.iter().enumerate() {
+            write_touch_row(f, &i.to_string(), t.npages, t.elapsed)?;
+        }
+        write_touch_row(f, "total", self.npages(), self.elapsed)?;
+
+        if let Some(latency) = self.latency() {
+            f.write_str("\nPAGE WRITE LATENCY\n")?;
+            f.write_fmt(format_args!("{}", latency))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_touch_row(
+    f: &mut std::fmt::Formatter<'_>,
+    label: &str,
+    npages: u64,
+    elapsed: Duration,
+) -> std::fmt::Result {
+    let secs = elapsed.as_secs_f64();
+    let (pages_per_sec, bytes_per_sec) = if secs > 0.0 {
+        let pages_per_sec = (npages as f64) / secs;
+        (pages_per_sec, pages_per_sec * (PAGE_SIZE as f64))
+    } else {
+        (0.0, 0.0)
+    };
+
+    f.write_fmt(format_args!(
+        "{:>6}  {:10}  {:11.3}  {:10.0}  {:7}\n",
+        label,
+        npages,
+        secs,
+        pages_per_sec,
+        ByteSizeDisplayGiB(ByteSize::b(bytes_per_sec as u64)),
+    ))
+}
+
+/// Keeps track of touches in progress (foreground or background) so that they
+/// can all be stopped at once (e.g., by a monitor alert)
+#[derive(Default)]
+pub(crate) struct ActiveTouches(Mutex<Vec<Arc<JobProgress>>>);
+
+impl ActiveTouches {
+    /// Records that a touch tracked by `progress` has started
+    ///
+    /// The touch remains registered until the returned guard is dropped.
+    pub fn register(
+        self: &Arc<Self>,
+        progress: &Arc<JobProgress>,
+    ) -> ActiveTouchGuard {
+        self.0.lock().unwrap().push(Arc::clone(progress));
+        ActiveTouchGuard {
+            touches: Arc::clone(self),
+            progress: Arc::clone(progress),
+        }
+    }
+
+    /// Cancels all touches in progress, returning how many there were
+    pub fn cancel_all(&self) -> usize {
+        let touches = self.0.lock().unwrap();
+        for progress in touches.iter() {
+            progress.cancel();
+        }
+        touches.len()
+    }
+}
+
+/// Represents a touch registered with [`ActiveTouches::register()`]
+pub(crate) struct ActiveTouchGuard {
+    touches: Arc<ActiveTouches>,
+    progress: Arc<JobProgress>,
+}
+
+impl Drop for ActiveTouchGuard {
+    fn drop(&mut self) {
+        self.touches
+            .0
+            .lock()
+            .unwrap()
+            .retain(|p| !Arc::ptr_eq(p, &self.progress));
+    }
+}
+
+/// Touch each page in the range `[start_addr, start_addr + size)`, stopping
+/// early if `progress` is cancelled
+///
+/// The work is split evenly across `options.nthreads` threads.  The caller is
+/// responsible for making sure the range remains mapped for the duration.
+pub fn touch_pages(
+    start_addr: usize,
+    size: usize,
+    options: &TouchOptions,
+    progress: &JobProgress,
+) -> TouchReport {
+    let npages = size.div_ceil(PAGE_SIZE);
+    let nthreads = options.nthreads.clamp(1, npages.max(1));
+    let thread_rate = options
+        .rate
+        .map(|rate| ByteSize::b((rate.as_u64() / (nthreads as u64)).max(1)));
+
+    let start = Instant::now();
+    let threads: Vec<TouchThreadReport> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..nthreads)
+            .map(|i| {
+                let first_page = i * npages / nthreads;
+                let last_page = (i + 1) * npages / nthreads;
+                let thread_start = start_addr + first_page * PAGE_SIZE;
+                let thread_end = start_addr + last_page * PAGE_SIZE;
+                scope.spawn(move || {
+                    touch_range(
+                        start_addr,
+                        thread_start..thread_end,
+                        thread_rate,
+                        options,
+                        progress,
+                    )
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("touch thread panicked"))
+            .collect()
+    });
+    let elapsed = start.elapsed();
+
+    // The touch may have been cancelled just as the last pages were touched,
+    // in which case it still completed.
+    let ntouched: u64 = threads.iter().map(|t| t.npages).sum();
+    let outcome = if ntouched < npages as u64 {
+        JobOutcome::Cancelled
+    } else {
+        JobOutcome::Completed
+    };
+
+    TouchReport { already_touched: false, outcome, elapsed, threads }
+}
+
+/// Touch each page in `range` from the current thread
+///
+/// `mapping_start` is used only to identify pages in latency samples.
+fn touch_range(
+    mapping_start: usize,
+    range: std::ops::Range<usize>,
+    rate: Option<ByteSize>,
+    options: &TouchOptions,
+    progress: &JobProgress,
+) -> TouchThreadReport {
+    let mut throttle = rate.map(|rate| TokenBucket::new(rate, PAGE_SIZE));
+    let mut latency = options.latency_sample.map(|_| Histogram::new());
+    let keep_samples = options.latency_file.is_some();
+    let mut latency_samples = Vec::new();
+    let sample_every = options.latency_sample.unwrap_or(1);
+    let mut npages = 0;
+    let start = Instant::now();
+
+    for page_addr in range.step_by(PAGE_SIZE) {
+        if progress.is_cancelled() {
+            break;
+        }
+        if let Some(throttle) = &mut throttle {
+            throttle.take(PAGE_SIZE);
+        }
+
+        let page_ptr: *mut u8 = page_addr as *mut u8;
+        match &mut latency {
+            Some(histogram) if npages % sample_every == 0 => {
+                let page_start = Instant::now();
+                unsafe { std::ptr::write(page_ptr, 1) };
+                let page_latency = page_start.elapsed();
+                histogram.record(page_latency);
+                if keep_samples {
+                    let page = (page_addr - mapping_start) / PAGE_SIZE;
+                    latency_samples.push((page, page_latency));
+                }
+            }
+            _ => unsafe { std::ptr::write(page_ptr, 1) },
+        }
+
+        progress.advance(PAGE_SIZE as u64);
+        npages += 1;
+    }
+
+    TouchThreadReport {
+        npages: npages as u64,
+        elapsed: start.elapsed(),
+        latency,
+        latency_samples,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::touch_pages;
+    use super::TouchOptions;
+    use crate::jobs::JobOutcome;
+    use crate::jobs::JobProgress;
+    use crate::PAGE_SIZE;
+
+    #[test]
+    fn test_touch_outcome() {
+        let mut buf = vec![0u8; 4 * PAGE_SIZE];
+        let addr = buf.as_mut_ptr() as usize;
+        let options = TouchOptions { nthreads: 2, ..TouchOptions::default() };
+
+        let progress = JobProgress::new(buf.len() as u64);
+        let report = touch_pages(addr, buf.len(), &options, &progress);
+        assert_eq!(report.outcome, JobOutcome::Completed);
+        assert_eq!(report.npages(), 4);
+        assert!(buf.iter().step_by(PAGE_SIZE).all(|b| *b == 1));
+
+        // Cancelling before any pages are touched stops the touch...
+        let progress = JobProgress::new(buf.len() as u64);
+        progress.cancel();
+        let report = touch_pages(addr, buf.len(), &options, &progress);
+        assert_eq!(report.outcome, JobOutcome::Cancelled);
+        assert_eq!(report.npages(), 0);
+
+        // ... but if there was nothing to do, the touch still completed.
+        let report = touch_pages(addr, 0, &options, &progress);
+        assert_eq!(report.outcome, JobOutcome::Completed);
+    }
+
+    #[test]
+    fn test_touch_size() {
+        let size = 10 * PAGE_SIZE;
+        assert_eq!(TouchOptions::default().touch_size(size), size);
+
+        let half = TouchOptions { percent: Some(50.0), ..Default::default() };
+        assert!(half.validate().is_ok());
+        assert_eq!(half.touch_size(size), 5 * PAGE_SIZE);
+        // Partial pages round up, but never past the end of the mapping.
+        assert_eq!(half.touch_size(3 * PAGE_SIZE), 2 * PAGE_SIZE);
+        assert_eq!(half.touch_size(PAGE_SIZE + 1), PAGE_SIZE);
+        let most = TouchOptions { percent: Some(75.0), ..Default::default() };
+        assert_eq!(most.touch_size(PAGE_SIZE + 1), PAGE_SIZE + 1);
+        let some = TouchOptions { percent: Some(1.0), ..Default::default() };
+        assert_eq!(some.touch_size(size), PAGE_SIZE);
+
+        for percent in [0.0, -5.0, 100.5, f64::NAN] {
+            let options =
+                TouchOptions { percent: Some(percent), ..Default::default() };
+            assert!(options.validate().is_err());
+        }
+    }
+}